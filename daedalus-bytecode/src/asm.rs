@@ -0,0 +1,359 @@
+//! Textual assembly, the format `dat_file::disasm` prints, parsed back into bytecode
+//!
+//! ```text
+//! func SIGN:
+//!   00000013  PushVar         SIGN.V
+//!   00000024  Bz              label_0
+//! label_0:
+//!   00000035  Call            OTHER
+//!   0000003a  PushVV          ARRAY[1] ; comment
+//! ```
+//!
+//! Addresses in front of instructions are optional and ignored, so instructions can be added and
+//! removed freely. `func`, `instance` and `prototype` headers mark where the code of a symbol
+//! starts, any other `name:` line is a label.
+
+use std::collections::HashMap;
+
+use num_traits::FromPrimitive as _;
+
+use crate::{Bytecode, Instruction, InstructionData, Label, Opcode, RelocationError};
+
+/// Symbol table names in the assembly get resolved against
+pub trait SymbolResolver {
+    fn symbol_id(&self, name: &str) -> Option<u32>;
+
+    /// Number of symbols, `#INDEX` has to be below it
+    fn symbol_count(&self) -> u32;
+
+    /// Whether a symbol is a function, instance or prototype with code, the only symbols a
+    /// header can be given for
+    fn has_code(&self, symbol: u32) -> bool;
+
+    /// Address of the code of a symbol, only asked for symbols that have no header in the
+    /// assembly
+    fn symbol_address(&self, symbol: u32) -> Option<u32>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AsmError {
+    #[error("line {line}: unknown opcode `{opcode}`")]
+    UnknownOpcode { line: usize, opcode: String },
+    #[error("line {line}: unknown symbol `{name}`")]
+    UnknownSymbol { line: usize, name: String },
+    #[error("line {line}: `{name}` is defined twice")]
+    DuplicateLabel { line: usize, name: String },
+    #[error("line {line}: `{name}` is not a function, instance or prototype with code")]
+    NotCode { line: usize, name: String },
+    #[error("line {line}: {reason}")]
+    Syntax { line: usize, reason: &'static str },
+    #[error(transparent)]
+    Relocation(#[from] RelocationError),
+}
+
+#[derive(Debug)]
+pub struct Assembly {
+    pub bytecode: Bytecode,
+    /// Symbols whose code is part of the assembly, along with the address it starts at
+    pub blocks: Vec<(u32, u32)>,
+}
+
+enum Line<'a> {
+    Block(&'a str),
+    Label(&'a str),
+    Instruction(Opcode, Option<&'a str>),
+}
+
+pub fn assemble(src: &str, symbols: &impl SymbolResolver) -> Result<Assembly, AsmError> {
+    let lines = src
+        .lines()
+        .enumerate()
+        .filter_map(|(id, line)| parse_line(id + 1, line).transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut bytecode = Bytecode::new();
+    let mut block = bytecode.block_builder();
+
+    // Labels can be jumped to before they are defined, so all of them are created upfront
+    let mut labels = HashMap::new();
+    for (line, item) in lines.iter() {
+        if let Line::Block(name) | Line::Label(name) = item {
+            if labels.insert(*name, block.new_label()).is_some() {
+                return Err(AsmError::DuplicateLabel {
+                    line: *line,
+                    name: name.to_string(),
+                });
+            }
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for (line, item) in lines.iter() {
+        let line = *line;
+        match item {
+            Line::Block(name) => {
+                let symbol = resolve(symbols, line, name)?;
+                if !symbols.has_code(symbol) {
+                    return Err(AsmError::NotCode {
+                        line,
+                        name: name.to_string(),
+                    });
+                }
+                blocks.push((symbol, block.next_address()));
+                block.bind_label(labels[name]);
+            }
+            Line::Label(name) => {
+                block.bind_label(labels[name]);
+            }
+            Line::Instruction(opcode, operand) => {
+                emit(&mut block, symbols, &labels, line, *opcode, *operand)?;
+            }
+        }
+    }
+
+    let local: HashMap<u32, u32> = blocks.iter().copied().collect();
+    bytecode.finalize(|symbol| {
+        local
+            .get(&symbol)
+            .copied()
+            .or_else(|| symbols.symbol_address(symbol))
+    })?;
+
+    Ok(Assembly { bytecode, blocks })
+}
+
+fn parse_line(line: usize, src: &str) -> Result<Option<(usize, Line<'_>)>, AsmError> {
+    let src = src.split(';').next().unwrap_or_default();
+    let mut tokens = src.split_whitespace().peekable();
+
+    // Address column of the disassembler
+    tokens.next_if(|token| token.len() == 8 && token.bytes().all(|b| b.is_ascii_hexdigit()));
+
+    let Some(first) = tokens.next() else {
+        return Ok(None);
+    };
+
+    let item = if let Some(name) = first.strip_suffix(':') {
+        Line::Label(name)
+    } else if matches!(first, "func" | "instance" | "prototype") {
+        let name = tokens
+            .next()
+            .and_then(|name| name.strip_suffix(':'))
+            .ok_or(AsmError::Syntax {
+                line,
+                reason: "expected `NAME:` after the kind of the block",
+            })?;
+        Line::Block(name)
+    } else {
+        let opcode = (0..=u8::MAX)
+            .filter_map(Opcode::from_u8)
+            .find(|opcode| format!("{opcode:?}") == first)
+            .ok_or_else(|| AsmError::UnknownOpcode {
+                line,
+                opcode: first.to_string(),
+            })?;
+        Line::Instruction(opcode, tokens.next())
+    };
+
+    if tokens.next().is_some() {
+        return Err(AsmError::Syntax {
+            line,
+            reason: "unexpected trailing tokens",
+        });
+    }
+
+    Ok(Some((line, item)))
+}
+
+fn emit(
+    block: &mut crate::BytecodeBlockBuilder,
+    symbols: &impl SymbolResolver,
+    labels: &HashMap<&str, Label>,
+    line: usize,
+    opcode: Opcode,
+    operand: Option<&str>,
+) -> Result<(), AsmError> {
+    let missing = || AsmError::Syntax {
+        line,
+        reason: "missing operand",
+    };
+
+    match opcode {
+        Opcode::Call | Opcode::B | Opcode::Bz => {
+            let target = operand.ok_or_else(missing)?;
+
+            if let Some(address) = parse_int(target) {
+                let instruction = Instruction {
+                    opcode,
+                    data: InstructionData::Address(address as u32),
+                };
+                block.push_instruction(instruction);
+            } else if opcode == Opcode::Call {
+                let symbol = resolve(symbols, line, target)?;
+                block.call_symbol(symbol);
+            } else {
+                let label = *labels.get(target).ok_or_else(|| AsmError::UnknownSymbol {
+                    line,
+                    name: target.to_string(),
+                })?;
+
+                if opcode == Opcode::B {
+                    block.jump(label);
+                } else {
+                    block.jump_if_zero(label);
+                }
+            }
+        }
+        Opcode::PushInt => {
+            let value = operand.ok_or_else(missing)?;
+            let value = parse_int(value).ok_or(AsmError::Syntax {
+                line,
+                reason: "expected an integer",
+            })?;
+            block.push_instruction(Instruction::push_int(value));
+        }
+        Opcode::CallExtern | Opcode::PushVar | Opcode::PushVarInstance | Opcode::GMovI => {
+            let symbol = resolve(symbols, line, operand.ok_or_else(missing)?)?;
+            block.push_instruction(Instruction {
+                opcode,
+                data: InstructionData::Symbol(symbol),
+            });
+        }
+        Opcode::PushVV => {
+            let operand = operand.ok_or_else(missing)?;
+            let invalid = AsmError::Syntax {
+                line,
+                reason: "expected `NAME[INDEX]`",
+            };
+
+            let (name, index) = operand
+                .strip_suffix(']')
+                .and_then(|operand| operand.split_once('['))
+                .ok_or(invalid)?;
+            let index = index.parse().map_err(|_| AsmError::Syntax {
+                line,
+                reason: "array index has to be in 0..=255",
+            })?;
+
+            let symbol = resolve(symbols, line, name)?;
+            block.push_instruction(Instruction::push_var_array(symbol, index));
+        }
+        _ => {
+            if operand.is_some() {
+                return Err(AsmError::Syntax {
+                    line,
+                    reason: "instruction takes no operand",
+                });
+            }
+            block.push_instruction(Instruction::operator(opcode));
+        }
+    }
+
+    Ok(())
+}
+
+/// Symbol name, or `#INDEX` for unnamed symbols
+fn resolve(symbols: &impl SymbolResolver, line: usize, name: &str) -> Result<u32, AsmError> {
+    let id = match name.strip_prefix('#') {
+        Some(id) => id.parse().ok().filter(|id| *id < symbols.symbol_count()),
+        None => symbols.symbol_id(name),
+    };
+
+    id.ok_or_else(|| AsmError::UnknownSymbol {
+        line,
+        name: name.to_string(),
+    })
+}
+
+/// Decimal or `0x` prefixed hex
+fn parse_int(src: &str) -> Option<i32> {
+    match src.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().map(|v| v as i32),
+        None => src.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Symbols;
+
+    impl SymbolResolver for Symbols {
+        fn symbol_id(&self, name: &str) -> Option<u32> {
+            ["MAIN", "OTHER", "ARRAY", "EXTERN"]
+                .iter()
+                .position(|symbol| *symbol == name)
+                .map(|id| id as u32)
+        }
+
+        fn symbol_count(&self) -> u32 {
+            4
+        }
+
+        fn has_code(&self, symbol: u32) -> bool {
+            symbol <= 1
+        }
+
+        fn symbol_address(&self, symbol: u32) -> Option<u32> {
+            (symbol == 1).then_some(0x100)
+        }
+    }
+
+    #[test]
+    fn instructions() {
+        let src = "
+            func MAIN:
+              00000000  PushInt         -5 ; comment
+                        Bz              end
+                        Call            OTHER
+                        Call            MAIN
+                        PushVV          ARRAY[3]
+                        CallExtern      #3
+            end:
+                        Return
+        ";
+
+        let assembly = assemble(src, &Symbols).unwrap();
+        assert_eq!(assembly.blocks, [(0, 0)]);
+
+        let instructions: Vec<_> = assembly.bytecode.instructions().collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::push_int(-5),
+                Instruction::jump_if_zero(31),
+                Instruction::call(0x100),
+                Instruction::call(0),
+                Instruction::push_var_array(2, 3),
+                Instruction::call_extern(3),
+                Instruction::ret(),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let err = |src| assemble(src, &Symbols).unwrap_err().to_string();
+
+        assert_eq!(err("\nJump end"), "line 2: unknown opcode `Jump`");
+        assert_eq!(err("PushVar NOPE"), "line 1: unknown symbol `NOPE`");
+        assert_eq!(err("B nowhere"), "line 1: unknown symbol `nowhere`");
+        assert_eq!(err("a:\na:"), "line 2: `a` is defined twice");
+        assert_eq!(err("CallExtern #4"), "line 1: unknown symbol `#4`");
+        assert_eq!(err("PushVar #x"), "line 1: unknown symbol `#x`");
+        assert_eq!(
+            err("\n\nfunc ARRAY:"),
+            "line 3: `ARRAY` is not a function, instance or prototype with code"
+        );
+        assert_eq!(
+            err("instance #2:"),
+            "line 1: `#2` is not a function, instance or prototype with code"
+        );
+        assert_eq!(err("Add 1"), "line 1: instruction takes no operand");
+        assert_eq!(
+            err("PushVV ARRAY[300]"),
+            "line 1: array index has to be in 0..=255"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Basic blocks of a single function and the jumps between them
+
+use std::{collections::BTreeSet, ops::Range};
+
+use crate::{Bytecode, Instruction, InstructionData, Opcode};
+
+/// Instructions that always run one after another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// Address of the first instruction
+    pub start: u32,
+    /// Address right after the last instruction
+    pub end: u32,
+    /// Along with their addresses
+    pub instructions: Vec<(u32, Instruction)>,
+    /// Indices of the blocks that can run next, empty for blocks ending with `Return`
+    pub successors: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CfgError {
+    #[error("invalid instruction at 0x{0:08x}")]
+    InvalidInstruction(u32),
+    #[error("jump at 0x{from:08x} lands in the middle of an instruction at 0x{to:08x}")]
+    MisalignedJump { from: u32, to: u32 },
+    #[error("jump at 0x{from:08x} leaves the function for 0x{to:08x}")]
+    JumpOutOfRange { from: u32, to: u32 },
+    #[error("code at 0x{0:08x} runs past the end of the function")]
+    FallsThrough(u32),
+}
+
+/// Control flow graph, the first block is the entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    /// Graph of the code in `range`, which has to start at an instruction
+    pub fn new(bytecode: &Bytecode, range: Range<u32>) -> Result<Self, CfgError> {
+        let instructions = bytecode
+            .decode_range(range.clone())
+            .map_err(|err| CfgError::InvalidInstruction(err.0))?;
+
+        let starts: BTreeSet<u32> = instructions.iter().map(|(address, _)| *address).collect();
+
+        // Blocks start at the entry, at jump targets and right after jumps
+        let mut leaders = BTreeSet::from([range.start]);
+        for (address, instruction) in instructions.iter() {
+            let next = address + instruction.size() as u32;
+
+            match (instruction.opcode, &instruction.data) {
+                (Opcode::B | Opcode::Bz, InstructionData::Address(target)) => {
+                    if !range.contains(target) {
+                        return Err(CfgError::JumpOutOfRange {
+                            from: *address,
+                            to: *target,
+                        });
+                    }
+                    if !starts.contains(target) {
+                        return Err(CfgError::MisalignedJump {
+                            from: *address,
+                            to: *target,
+                        });
+                    }
+                    leaders.insert(*target);
+                    leaders.insert(next);
+                }
+                (Opcode::Return, _) => {
+                    leaders.insert(next);
+                }
+                _ => {}
+            }
+        }
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        for (address, instruction) in instructions {
+            let end = address + instruction.size() as u32;
+
+            match blocks.last_mut() {
+                Some(block) if !leaders.contains(&address) => {
+                    block.end = end;
+                    block.instructions.push((address, instruction));
+                }
+                _ => blocks.push(BasicBlock {
+                    start: address,
+                    end,
+                    instructions: vec![(address, instruction)],
+                    successors: Vec::new(),
+                }),
+            }
+        }
+
+        let index_of = |address: u32| {
+            blocks
+                .binary_search_by_key(&address, |block| block.start)
+                .expect("jump targets start a block")
+        };
+
+        let successors: Vec<Vec<usize>> = blocks
+            .iter()
+            .map(|block| {
+                let (address, last) = block.instructions.last().expect("blocks are never empty");
+                let fallthrough = || {
+                    if block.end < range.end {
+                        Ok(index_of(block.end))
+                    } else {
+                        Err(CfgError::FallsThrough(*address))
+                    }
+                };
+
+                Ok(match (last.opcode, &last.data) {
+                    (Opcode::Return, _) => Vec::new(),
+                    (Opcode::B, InstructionData::Address(target)) => vec![index_of(*target)],
+                    (Opcode::Bz, InstructionData::Address(target)) => {
+                        let mut successors = vec![fallthrough()?, index_of(*target)];
+                        successors.dedup();
+                        successors
+                    }
+                    _ => vec![fallthrough()?],
+                })
+            })
+            .collect::<Result<_, CfgError>>()?;
+
+        for (block, successors) in blocks.iter_mut().zip(successors) {
+            block.successors = successors;
+        }
+
+        Ok(Self { blocks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{assemble, SymbolResolver};
+
+    struct Symbols;
+
+    impl SymbolResolver for Symbols {
+        fn symbol_id(&self, _: &str) -> Option<u32> {
+            Some(0)
+        }
+
+        fn symbol_count(&self) -> u32 {
+            1
+        }
+
+        fn has_code(&self, _: u32) -> bool {
+            true
+        }
+
+        fn symbol_address(&self, _: u32) -> Option<u32> {
+            None
+        }
+    }
+
+    fn cfg(src: &str) -> Result<Cfg, CfgError> {
+        let bytecode = assemble(src, &Symbols).unwrap().bytecode;
+        let len = bytecode.as_bytes().len() as u32;
+        Cfg::new(&bytecode, 0..len)
+    }
+
+    #[test]
+    fn blocks() {
+        let cfg = cfg("
+                PushInt 1
+                Bz      else
+                PushInt 2
+                B       end
+            else:
+                PushInt 3
+            end:
+                Return
+        ")
+        .unwrap();
+
+        let ranges: Vec<_> = cfg.blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(ranges, [(0, 10), (10, 20), (20, 25), (25, 26)]);
+
+        let successors: Vec<_> = cfg.blocks.iter().map(|b| b.successors.clone()).collect();
+        assert_eq!(successors, [vec![1, 2], vec![3], vec![3], vec![]]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            cfg("B 0x3\nReturn"),
+            Err(CfgError::MisalignedJump { from: 0, to: 3 })
+        );
+        assert_eq!(
+            cfg("Bz 0x100\nReturn"),
+            Err(CfgError::JumpOutOfRange { from: 0, to: 0x100 })
+        );
+        assert_eq!(cfg("Return\nPushInt 1"), Err(CfgError::FallsThrough(1)));
+
+        let mut bytecode = Bytecode::new();
+        bytecode.block(&[Instruction::push_int(1)]);
+        assert_eq!(
+            Cfg::new(&bytecode, 0..3),
+            Err(CfgError::InvalidInstruction(0))
+        );
+    }
+}
@@ -1,17 +1,29 @@
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::FromPrimitive as _;
-use std::io::{self, Cursor, Read, Write};
+use std::{
+    io::{self, Cursor, Read, Write},
+    ops::Range,
+};
+
+pub mod asm;
+pub mod cfg;
 
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct Bytecode {
     bytecode: Vec<u8>,
+    /// Addresses of labels, `None` until the label gets bound
+    labels: Vec<Option<u32>>,
+    /// Operands that still need to be resolved by [`Bytecode::finalize`]
+    relocations: Vec<Relocation>,
 }
 
 impl Bytecode {
     pub fn new() -> Self {
         Self {
             bytecode: Vec::new(),
+            labels: Vec::new(),
+            relocations: Vec::new(),
         }
     }
 
@@ -32,8 +44,33 @@ impl Bytecode {
     pub fn block_builder(&mut self) -> BytecodeBlockBuilder<'_> {
         BytecodeBlockBuilder {
             addr: self.next_available_address(),
-            bytecode: &mut self.bytecode,
+            bytecode: self,
+        }
+    }
+
+    /// Resolve all label and symbol references, `symbol_address` is used to look up the address of
+    /// functions referenced by `Call` instructions
+    pub fn finalize(
+        &mut self,
+        symbol_address: impl Fn(u32) -> Option<u32>,
+    ) -> Result<(), RelocationError> {
+        for relocation in std::mem::take(&mut self.relocations) {
+            // Address of the instruction, not of the operand, for nicer errors
+            let address = relocation.offset - std::mem::size_of::<u8>() as u32;
+
+            let target = match relocation.target {
+                RelocationTarget::Label(label) => self.labels[label.0 as usize]
+                    .ok_or(RelocationError::UnboundLabel { label, address })?,
+                RelocationTarget::Symbol(symbol) => symbol_address(symbol)
+                    .ok_or(RelocationError::UnresolvedSymbol { symbol, address })?,
+            };
+
+            let offset = relocation.offset as usize;
+            self.bytecode[offset..offset + std::mem::size_of::<u32>()]
+                .copy_from_slice(&target.to_le_bytes());
         }
+
+        Ok(())
     }
 
     pub fn decode(mut r: impl Read) -> io::Result<Self> {
@@ -41,10 +78,18 @@ impl Bytecode {
         let mut bytecode = vec![0; len];
         r.read_exact(&mut bytecode)?;
 
-        Ok(Self { bytecode })
+        Ok(Self {
+            bytecode,
+            ..Self::default()
+        })
     }
 
     pub fn encode(&self, mut w: impl Write) -> io::Result<usize> {
+        debug_assert!(
+            self.relocations.is_empty(),
+            "Bytecode::finalize was not called before encode"
+        );
+
         w.write_u32::<LittleEndian>(self.bytecode.len() as u32)
             .unwrap();
         w.write_all(&self.bytecode).unwrap();
@@ -52,27 +97,101 @@ impl Bytecode {
         Ok(std::mem::size_of::<u32>() + std::mem::size_of::<u8>() * self.bytecode.len())
     }
 
+    /// All of the code, decoding stops at the first invalid instruction, use
+    /// [`Bytecode::decode_range`] to find out about it
     pub fn instructions(&self) -> impl Iterator<Item = Instruction> + '_ {
         let mut r = Cursor::new(&self.bytecode);
         std::iter::from_fn(move || {
             if r.position() as usize >= self.bytecode.len() {
                 None
             } else {
-                Some(Instruction::decode(&mut r).unwrap())
+                Instruction::decode(&mut r).ok()
             }
         })
+        .fuse()
+    }
+
+    /// Instructions in `range` along with their addresses, `range` has to start at an instruction
+    pub fn decode_range(
+        &self,
+        range: Range<u32>,
+    ) -> Result<Vec<(u32, Instruction)>, InvalidInstruction> {
+        let code = self
+            .bytecode
+            .get(range.start as usize..range.end as usize)
+            .ok_or(InvalidInstruction(range.start))?;
+
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < code.len() {
+            let address = range.start + offset as u32;
+            let instruction =
+                Instruction::decode(&code[offset..]).map_err(|_| InvalidInstruction(address))?;
+
+            offset += instruction.size();
+            instructions.push((address, instruction));
+        }
+
+        Ok(instructions)
+    }
+}
+
+/// Unknown opcode, or an operand cut off by the end of the code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("invalid instruction at 0x{0:08x}")]
+pub struct InvalidInstruction(pub u32);
+
+/// Symbolic jump target, see [`BytecodeBlockBuilder::new_label`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RelocationTarget {
+    Label(Label),
+    /// Address of the function with given symbol index
+    Symbol(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Relocation {
+    /// Offset of the address operand in the bytecode
+    offset: u32,
+    target: RelocationTarget,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RelocationError {
+    #[error("Jump at 0x{address:x} targets a label that was never bound")]
+    UnboundLabel { label: Label, address: u32 },
+    #[error("Call at 0x{address:x} targets symbol {symbol} that has no address")]
+    UnresolvedSymbol { symbol: u32, address: u32 },
+}
+
+impl RelocationError {
+    /// Address of the instruction that could not be resolved
+    pub fn address(&self) -> u32 {
+        match self {
+            RelocationError::UnboundLabel { address, .. }
+            | RelocationError::UnresolvedSymbol { address, .. } => *address,
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct BytecodeBlockBuilder<'a> {
     addr: u32,
-    bytecode: &'a mut Vec<u8>,
+    bytecode: &'a mut Bytecode,
 }
 
 impl<'a> BytecodeBlockBuilder<'a> {
     fn encode(&mut self, i: &Instruction) {
-        i.encode(&mut self.bytecode).unwrap();
+        i.encode(&mut self.bytecode.bytecode).unwrap();
+    }
+
+    fn encode_relocated(&mut self, i: &Instruction, target: RelocationTarget) {
+        let offset = self.next_address() + std::mem::size_of::<u8>() as u32;
+        self.bytecode.relocations.push(Relocation { offset, target });
+        self.encode(i);
     }
 
     pub fn push_instruction(&mut self, instruction: Instruction) -> &mut Self {
@@ -103,17 +222,47 @@ impl<'a> BytecodeBlockBuilder<'a> {
         self
     }
 
-    pub fn instance_assign_int(&mut self, (array_symbol, id): (u32, u8), value: i32) -> &mut Self {
-        self.encode(&Instruction::push_int(value));
-        if id == 0 {
-            self.encode(&Instruction::push_var(array_symbol));
-        } else {
-            self.encode(&Instruction::push_var_array(array_symbol, id));
-        }
-        self.encode(&Instruction::mov_int());
+    /// Create a new label, it can be jumped to before it is bound to an address with
+    /// [`BytecodeBlockBuilder::bind_label`]
+    pub fn new_label(&mut self) -> Label {
+        let label = Label(self.bytecode.labels.len() as u32);
+        self.bytecode.labels.push(None);
+        label
+    }
+
+    /// Bind the label to the address of the next instruction
+    pub fn bind_label(&mut self, label: Label) -> &mut Self {
+        let address = self.next_address();
+        let slot = &mut self.bytecode.labels[label.0 as usize];
+        assert!(slot.is_none(), "{label:?} is already bound");
+        *slot = Some(address);
+        self
+    }
+
+    pub fn jump(&mut self, label: Label) -> &mut Self {
+        self.encode_relocated(&Instruction::jump(0), RelocationTarget::Label(label));
+        self
+    }
+
+    pub fn jump_if_zero(&mut self, label: Label) -> &mut Self {
+        self.encode_relocated(
+            &Instruction::jump_if_zero(0),
+            RelocationTarget::Label(label),
+        );
+        self
+    }
+
+    /// Call the function with given symbol index, the function does not have to be emitted yet
+    pub fn call_symbol(&mut self, symbol: u32) -> &mut Self {
+        self.encode_relocated(&Instruction::call(0), RelocationTarget::Symbol(symbol));
         self
     }
 
+    /// Address of the next instruction that will be pushed to the block
+    pub fn next_address(&self) -> u32 {
+        self.bytecode.next_available_address()
+    }
+
     pub fn addr(&self) -> u32 {
         self.addr
     }
@@ -127,7 +276,13 @@ pub struct Instruction {
 
 impl Instruction {
     pub fn decode(mut r: impl Read) -> std::io::Result<Self> {
-        let opcode = Opcode::from_u8(r.read_u8()?).unwrap();
+        let opcode = r.read_u8()?;
+        let opcode = Opcode::from_u8(opcode).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown opcode {opcode}"),
+            )
+        })?;
         let data = match opcode {
             Opcode::Call | Opcode::Bz | Opcode::B => {
                 let a = r.read_u32::<LittleEndian>()?;
@@ -184,6 +339,14 @@ impl Instruction {
         std::mem::size_of::<u8>() + data_size
     }
 
+    /// Instruction without an operand, eg. arithmetic or comparison
+    pub fn operator(opcode: Opcode) -> Self {
+        Self {
+            opcode,
+            data: InstructionData::None,
+        }
+    }
+
     pub fn push_int(immediate: i32) -> Self {
         Self {
             opcode: Opcode::PushInt,
@@ -194,7 +357,7 @@ impl Instruction {
     pub fn push_var(symbol: u32) -> Self {
         Self {
             opcode: Opcode::PushVar,
-            data: InstructionData::Immediate(symbol as i32),
+            data: InstructionData::Symbol(symbol),
         }
     }
 
@@ -205,6 +368,34 @@ impl Instruction {
         }
     }
 
+    pub fn mov_float() -> Self {
+        Self {
+            opcode: Opcode::MovF,
+            data: InstructionData::None,
+        }
+    }
+
+    pub fn mov_string() -> Self {
+        Self {
+            opcode: Opcode::MovS,
+            data: InstructionData::None,
+        }
+    }
+
+    pub fn mov_func() -> Self {
+        Self {
+            opcode: Opcode::MovVF,
+            data: InstructionData::None,
+        }
+    }
+
+    pub fn mov_instance() -> Self {
+        Self {
+            opcode: Opcode::MovVI,
+            data: InstructionData::None,
+        }
+    }
+
     pub fn push_var_array(symbol: u32, index: u8) -> Self {
         Self {
             opcode: Opcode::PushVV,
@@ -219,6 +410,14 @@ impl Instruction {
         }
     }
 
+    /// Make the instance behind `symbol` the current one, fields pushed afterwards belong to it
+    pub fn set_instance(symbol: u32) -> Self {
+        Self {
+            opcode: Opcode::GMovI,
+            data: InstructionData::Symbol(symbol),
+        }
+    }
+
     pub fn call_extern(symbol: u32) -> Self {
         Self {
             opcode: Opcode::CallExtern,
@@ -226,10 +425,24 @@ impl Instruction {
         }
     }
 
-    pub fn call(symbol: u32) -> Self {
+    pub fn call(address: u32) -> Self {
         Self {
             opcode: Opcode::Call,
-            data: InstructionData::Symbol(symbol),
+            data: InstructionData::Address(address),
+        }
+    }
+
+    pub fn jump(address: u32) -> Self {
+        Self {
+            opcode: Opcode::B,
+            data: InstructionData::Address(address),
+        }
+    }
+
+    pub fn jump_if_zero(address: u32) -> Self {
+        Self {
+            opcode: Opcode::Bz,
+            data: InstructionData::Address(address),
         }
     }
 
@@ -400,3 +613,68 @@ pub enum Opcode {
     /// instruction onto the stack as a reference.
     PushVV = 245,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relocations() {
+        let mut bytecode = Bytecode::new();
+
+        let mut block = bytecode.block_builder();
+        let end = block.new_label();
+        block
+            .push_instruction(Instruction::push_int(1))
+            .jump_if_zero(end)
+            .call_symbol(7)
+            .bind_label(end)
+            .ret();
+
+        bytecode.finalize(|symbol| (symbol == 7).then_some(42)).unwrap();
+
+        let instructions: Vec<_> = bytecode.instructions().collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::push_int(1),
+                Instruction::jump_if_zero(15),
+                Instruction::call(42),
+                Instruction::ret(),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_instructions() {
+        let mut bytecode = Bytecode::new();
+        bytecode.block(&[Instruction::push_int(1), Instruction::ret()]);
+        bytecode.bytecode.extend([0xEE, Opcode::Return as u8]);
+
+        let instructions: Vec<_> = bytecode.instructions().collect();
+        assert_eq!(instructions, [Instruction::push_int(1), Instruction::ret()]);
+
+        assert_eq!(
+            bytecode.decode_range(0..6),
+            Ok(vec![(0, Instruction::push_int(1)), (5, Instruction::ret())])
+        );
+        assert_eq!(bytecode.decode_range(0..8), Err(InvalidInstruction(6)));
+        assert_eq!(bytecode.decode_range(0..3), Err(InvalidInstruction(0)));
+        assert_eq!(bytecode.decode_range(6..100), Err(InvalidInstruction(6)));
+    }
+
+    #[test]
+    fn unbound_label() {
+        let mut bytecode = Bytecode::new();
+
+        let mut block = bytecode.block_builder();
+        let label = block.new_label();
+        block.ret().jump(label);
+
+        let err = bytecode.finalize(|_| None).unwrap_err();
+        assert!(matches!(
+            err,
+            RelocationError::UnboundLabel { address: 1, .. }
+        ));
+    }
+}
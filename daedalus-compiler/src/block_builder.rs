@@ -0,0 +1,552 @@
+use std::collections::HashMap;
+
+use daedalus_bytecode::{BytecodeBlockBuilder, Instruction, Opcode};
+use daedalus_parser::{
+    AssocOp, Block, BlockItem, Expr, ExprKind, FunctionCall, Ident, IfStatement, LitKind,
+    ReturnStatement, UnaryOp, Var, VarKind,
+};
+use dat_file::properties::DataType;
+use logos::Span;
+use zstring::ZString;
+
+use crate::{
+    const_eval::{ConstValues, Value},
+    dat_symbol_table::DatSymbolTable,
+    error::CompileError,
+    files::FileId,
+    symbol_indices::{SymbolIndex, SymbolIndices, SymbolKind},
+    type_check::int_literal,
+};
+
+/// Variable a value is read from, or written to
+#[derive(Debug, Clone, Copy)]
+struct Place {
+    /// Instance that has to be made current first, set for fields accessed through an instance
+    instance: Option<u32>,
+    symbol: SymbolIndex,
+    /// Array element
+    id: u8,
+}
+
+impl Place {
+    fn new(symbol: SymbolIndex) -> Self {
+        Self {
+            instance: None,
+            symbol,
+            id: 0,
+        }
+    }
+}
+
+/// Emits bytecode of a single instance or function body
+pub struct BlockBuilder<'a, 'b> {
+    pub file: FileId,
+    /// Prefix of the symbols visible only inside of the block, name of the class for instance
+    /// bodies, or name of the function for function bodies
+    pub scope: &'a str,
+    /// Symbol of the instance that is being initialized, `None` in function bodies
+    pub this: Option<u32>,
+    /// `this` is a prototype, which `GMovI` can't make current again once another instance was
+    pub prototype: bool,
+    pub symbol_indices: &'a SymbolIndices,
+    pub const_values: &'a ConstValues,
+    pub symbol_table: &'a mut DatSymbolTable,
+    pub block: &'a mut BytecodeBlockBuilder<'b>,
+    /// Source of every emitted `Call`, by its address
+    pub call_sites: &'a mut HashMap<u32, (FileId, Span)>,
+    /// Statements that fail to compile are skipped, so that errors of the whole block get reported
+    pub errors: &'a mut Vec<CompileError>,
+}
+
+impl<'a, 'b> BlockBuilder<'a, 'b> {
+    pub fn visit_block(&mut self, block: &Block) {
+        for item in block.items.iter() {
+            if let Err(err) = self.visit_block_item(item) {
+                self.errors.push(err);
+            }
+        }
+    }
+
+    fn visit_block_item(&mut self, item: &BlockItem) -> Result<(), CompileError> {
+        match item {
+            BlockItem::Expr(expr) => self.visit_expr(expr),
+            BlockItem::Var(var) => self.visit_local(var),
+            BlockItem::Return(ret) => self.visit_return(ret),
+            BlockItem::If(stmt) => self.visit_if(stmt),
+        }
+    }
+
+    /// Function arguments are passed on the stack, so on entry they get popped into their symbols,
+    /// last argument first
+    pub fn pop_args(&mut self, args: &[Var]) -> Result<(), CompileError> {
+        for var in args.iter().rev() {
+            let symbol = self.visit_reference(&var.ident)?;
+
+            self.push_reference(symbol, 0);
+            let mov = self.mov_instruction(symbol.ty, &var.span)?;
+            self.block.push_instruction(mov);
+        }
+        Ok(())
+    }
+
+    /// Symbols of locals are emitted upfront, only the initializer needs code
+    fn visit_local(&mut self, var: &Var) -> Result<(), CompileError> {
+        match &var.kind {
+            VarKind::Value { init: Some(init) } => {
+                let symbol = self.visit_reference(&var.ident)?;
+                self.assign(Place::new(symbol), &var.ident.span, init)
+            }
+            VarKind::Value { init: None } | VarKind::Array { init: None, .. } => Ok(()),
+            VarKind::Array { init: Some(_), .. } => {
+                Err(self.unsupported("array initializer", &var.span))
+            }
+        }
+    }
+
+    // if cond_a {
+    //     body_a
+    // } else if cond_b {
+    //     body_b
+    // } else {
+    //     body_c
+    // };
+    //
+    // Gets lowered the same way zengin does it:
+    //
+    //     cond_a
+    //     Bz next_1
+    //     body_a
+    //     B end
+    // next_1:
+    //     cond_b
+    //     Bz next_2
+    //     body_b
+    //     B end
+    // next_2:
+    //     body_c
+    // end:
+    fn visit_if(&mut self, stmt: &IfStatement) -> Result<(), CompileError> {
+        let end = self.block.new_label();
+
+        let mut stmt = Some(stmt);
+        while let Some(branch) = stmt {
+            let next_branch = self.block.new_label();
+
+            if let Some(condition) = branch.condition.as_ref() {
+                self.push_value(condition)?;
+                self.block.jump_if_zero(next_branch);
+            }
+
+            self.visit_block(&branch.block);
+
+            if branch.next.is_some() {
+                self.block.jump(end);
+            }
+
+            self.block.bind_label(next_branch);
+            stmt = branch.next.as_deref();
+        }
+
+        self.block.bind_label(end);
+        Ok(())
+    }
+
+    fn visit_return(&mut self, ret: &ReturnStatement) -> Result<(), CompileError> {
+        if let Some(expr) = ret.expr.as_ref() {
+            // Scope of a function body is the name of the function
+            match self.symbol_indices.get(self.scope) {
+                Some(function) if self.this.is_none() => {
+                    self.push_typed_value(expr, function.ty)?
+                }
+                _ => self.push_value(expr)?,
+            }
+        }
+        self.block.ret();
+        Ok(())
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        match &expr.kind {
+            ExprKind::Binary(op, left, right) if is_assign(op) => {
+                self.visit_assign(op, left, right)
+            }
+            ExprKind::Call(call) => self.visit_call(call),
+            // Result of the expression is left on the stack, just like zengin does it
+            _ => self.push_value(expr),
+        }
+    }
+
+    // a = b
+    // a += b
+    fn visit_assign(
+        &mut self,
+        op: &AssocOp,
+        left: &Expr,
+        right: &Expr,
+    ) -> Result<(), CompileError> {
+        // "C_NPC.ATTRIBUTE"
+        let target = self.visit_place(left)?;
+
+        if *op == AssocOp::Assign {
+            return self.assign(target, &left.span, right);
+        }
+
+        if target.symbol.ty != DataType::Int {
+            return Err(self.unsupported("compound assignment to non-intager", &left.span));
+        }
+
+        self.push_value(right)?;
+        self.push_place(target);
+        self.block
+            .push_instruction(Instruction::operator(binary_opcode(op)));
+        Ok(())
+    }
+
+    /// The move instruction is picked based on the type of the target, strings get copied with
+    /// `MovS`, functions and instances are stored as references with `MovVF` and `MovVI`
+    fn assign(
+        &mut self,
+        target: Place,
+        target_span: &Span,
+        value: &Expr,
+    ) -> Result<(), CompileError> {
+        let mov = self.mov_instruction(target.symbol.ty, target_span)?;
+
+        self.push_typed_value(value, target.symbol.ty)?;
+        self.push_place(target);
+        self.block.push_instruction(mov);
+        Ok(())
+    }
+
+    fn mov_instruction(&self, ty: DataType, span: &Span) -> Result<Instruction, CompileError> {
+        Ok(match ty {
+            DataType::Float => Instruction::mov_float(),
+            DataType::String => Instruction::mov_string(),
+            DataType::Func => Instruction::mov_func(),
+            DataType::Instance => Instruction::mov_instance(),
+            DataType::Int => Instruction::mov_int(),
+            DataType::Void | DataType::Class | DataType::Prototype => {
+                return Err(self.unsupported("assignment to this type", span))
+            }
+        })
+    }
+
+    /// Left hand side of an assignment, `a`, `npc.a` or `a[1]`
+    fn visit_place(&self, expr: &Expr) -> Result<Place, CompileError> {
+        match &expr.kind {
+            ExprKind::Ident(_) | ExprKind::Field(_, _) => self.visit_symbol(expr),
+            ExprKind::Index(symbol, id) => Ok(Place {
+                id: self.visit_array_index(id)?,
+                ..self.visit_symbol(symbol)?
+            }),
+            _ => Err(self.unsupported("assignment to this expression", &expr.span)),
+        }
+    }
+
+    /// Variable named by `a`, or a field accessed through an instance with `npc.a`
+    ///
+    /// In instance bodies `self` is the instance that is being initialized, so its fields are
+    /// accessed just like the unqualified ones, any other instance has to be made current first
+    /// and the initialized one current again afterwards
+    fn visit_symbol(&self, expr: &Expr) -> Result<Place, CompileError> {
+        match &expr.kind {
+            ExprKind::Ident(ident) => Ok(Place::new(self.visit_reference(ident)?)),
+            ExprKind::Field(base, field) if self.is_this(base) => {
+                Ok(Place::new(self.visit_field(self.scope, field)?))
+            }
+            ExprKind::Field(base, field) => {
+                let ExprKind::Ident(ident) = &base.kind else {
+                    return Err(self.unsupported("field access on this expression", &base.span));
+                };
+                if self.prototype {
+                    return Err(self
+                        .unsupported("field access on other instances in prototypes", &expr.span));
+                }
+
+                let instance = self.visit_reference(ident)?;
+                let class = self
+                    .instance_class(ident)
+                    .ok_or_else(|| self.unsupported("field access on non-instance", &base.span))?;
+
+                Ok(Place {
+                    instance: Some(instance.id),
+                    ..Place::new(self.visit_field(class, field)?)
+                })
+            }
+            _ => Err(self.unsupported("indexing of this expression", &expr.span)),
+        }
+    }
+
+    fn visit_field(&self, class: &str, field: &Ident) -> Result<SymbolIndex, CompileError> {
+        self.symbol_indices
+            .get(&format!("{class}.{}", field.raw.to_uppercase()))
+            .copied()
+            .ok_or_else(|| CompileError::UnknownIdent {
+                ident: field.raw.clone(),
+                file: self.file,
+                span: field.span.clone(),
+            })
+    }
+
+    /// Class of an instance, or of an instance typed variable, resolved the same way as
+    /// [`Self::visit_reference`]
+    fn instance_class(&self, ident: &Ident) -> Option<&'a str> {
+        let name = ident.raw.to_uppercase();
+        let scoped = format!("{}.{name}", self.scope);
+
+        if self.symbol_indices.contains_key(&scoped) {
+            self.symbol_indices.instance_class(&scoped)
+        } else {
+            self.symbol_indices.instance_class(&name)
+        }
+    }
+
+    fn is_this(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Ident(ident) => {
+                self.this.is_some() && matches!(ident.raw.to_uppercase().as_str(), "SELF" | "THIS")
+            }
+            _ => false,
+        }
+    }
+
+    /// Array indices are part of the instruction, so they have to be known at compile time
+    fn visit_array_index(&self, expr: &Expr) -> Result<u8, CompileError> {
+        let id = match &expr.kind {
+            ExprKind::Lit(lit) => match lit.kind {
+                LitKind::Intager(id) => Some(id),
+                _ => None,
+            },
+            ExprKind::Ident(ident) => match self.const_values.map.get(&ident.raw.to_uppercase()) {
+                Some(Value::Int(id)) => Some(*id),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        id.and_then(|id| u8::try_from(id).ok())
+            .ok_or_else(|| CompileError::InvalidArrayIndex {
+                file: self.file,
+                span: expr.span.clone(),
+            })
+    }
+
+    fn unsupported(&self, what: &'static str, span: &Span) -> CompileError {
+        CompileError::Unsupported {
+            what,
+            file: self.file,
+            span: span.clone(),
+        }
+    }
+
+    fn push_place(&mut self, place: Place) {
+        if let Some(instance) = place.instance {
+            self.block
+                .push_instruction(Instruction::set_instance(instance));
+        }
+        self.push_reference(place.symbol, place.id);
+
+        // Unqualified fields of the instance body would resolve against `instance` otherwise
+        if let (Some(_), Some(this)) = (place.instance, self.this) {
+            self.block.push_instruction(Instruction::set_instance(this));
+        }
+    }
+
+    fn push_reference(&mut self, symbol: SymbolIndex, id: u8) {
+        self.block.push_instruction(if id != 0 {
+            Instruction::push_var_array(symbol.id, id)
+        } else if symbol.ty == DataType::Instance {
+            Instruction::push_var_instance(symbol.id)
+        } else {
+            Instruction::push_var(symbol.id)
+        });
+    }
+
+    fn visit_reference(&self, ident: &Ident) -> Result<SymbolIndex, CompileError> {
+        let name = ident.raw.to_uppercase();
+
+        match (name.as_str(), self.this) {
+            ("SELF" | "THIS", Some(this)) => Ok(SymbolIndex {
+                id: this,
+                kind: SymbolKind::Instance,
+                ty: DataType::Instance,
+            }),
+            (name, _) => self
+                .symbol_indices
+                .get(&format!("{}.{name}", self.scope))
+                .or_else(|| self.symbol_indices.get(name))
+                .copied()
+                .ok_or_else(|| CompileError::UnknownIdent {
+                    ident: ident.raw.clone(),
+                    file: self.file,
+                    span: ident.span.clone(),
+                }),
+        }
+    }
+
+    /// Value that ends up in a variable of type `ty`, int literals are turned into floats for
+    /// float typed ones
+    fn push_typed_value(&mut self, value: &Expr, ty: DataType) -> Result<(), CompileError> {
+        match int_literal(value) {
+            Some(v) if ty == DataType::Float => {
+                self.block
+                    .push_instruction(Instruction::push_int((v as f32).to_bits() as i32));
+                Ok(())
+            }
+            _ => self.push_value(value),
+        }
+    }
+
+    fn push_value(&mut self, arg: &Expr) -> Result<(), CompileError> {
+        match &arg.kind {
+            ExprKind::Ident(arg) => {
+                let symbol = self.visit_reference(arg)?;
+                match symbol.kind {
+                    SymbolKind::Instance => {
+                        self.block
+                            .push_instruction(Instruction::push_var_instance(symbol.id));
+                    }
+                    // Functions are passed around as their symbol index
+                    SymbolKind::Function | SymbolKind::ExternFunction => {
+                        self.block
+                            .push_instruction(Instruction::push_int(symbol.id as i32));
+                    }
+                    SymbolKind::Other => self.push_reference(symbol, 0),
+                }
+            }
+            ExprKind::Lit(lit) => match &lit.kind {
+                LitKind::Intager(v) => {
+                    self.block.push_instruction(Instruction::push_int(v.abs()));
+                    if v.is_negative() {
+                        self.block.push_instruction(Instruction::negate());
+                    }
+                }
+                LitKind::Float(v) => {
+                    // Well that's fun, it turns out floats were ints all along
+                    let v = v.to_le_bytes();
+                    let v = i32::from_le_bytes(v);
+                    self.block.push_instruction(Instruction::push_int(v));
+                }
+                LitKind::String(v) => {
+                    self.block.push_instruction(Instruction::push_var(
+                        self.symbol_table.string(ZString::from(v.as_bytes())),
+                    ));
+                }
+            },
+            ExprKind::Index(symbol, id) => {
+                let place = Place {
+                    id: self.visit_array_index(id)?,
+                    ..self.visit_symbol(symbol)?
+                };
+                self.push_place(place);
+            }
+            // Operands are pushed in reverse, so that the left one ends up on top of the stack
+            ExprKind::Binary(op, left, right) => {
+                if is_assign(op) {
+                    return Err(self.unsupported("assignment used as a value", &arg.span));
+                }
+
+                self.push_value(right)?;
+                self.push_value(left)?;
+                self.block
+                    .push_instruction(Instruction::operator(binary_opcode(op)));
+            }
+            ExprKind::Unary(op, expr) => {
+                self.push_value(expr)?;
+                self.block.push_instruction(Instruction::operator(match op {
+                    UnaryOp::Not => Opcode::Not,
+                    UnaryOp::Negative => Opcode::Negate,
+                }));
+            }
+            ExprKind::Call(call) => self.visit_call(call)?,
+            ExprKind::Paren(expr) => self.push_value(expr)?,
+            ExprKind::Field(_, _) => {
+                let place = self.visit_symbol(arg)?;
+                self.push_place(place);
+            }
+        };
+        Ok(())
+    }
+
+    // Mdl_SetVisual(self, "HUMANS.MDS")
+    // Mdl_SetVisualBody(self, "hum_body_Naked0", 9, 0, "Hum_Head_Pony", 18, 0, -1);
+    fn visit_call(&mut self, call: &FunctionCall) -> Result<(), CompileError> {
+        let ident = call.ident.raw.to_uppercase();
+
+        let symbol =
+            *self
+                .symbol_indices
+                .get(&ident)
+                .ok_or_else(|| CompileError::UnknownIdent {
+                    ident: call.ident.raw.clone(),
+                    file: self.file,
+                    span: call.ident.span.clone(),
+                })?;
+
+        let arg_types = self.symbol_indices.arg_types(&ident).unwrap_or_default();
+        for (i, arg) in call.args.iter().enumerate() {
+            match arg_types.get(i) {
+                Some(&ty) => self.push_typed_value(arg, ty)?,
+                None => self.push_value(arg)?,
+            }
+        }
+
+        match symbol.kind {
+            SymbolKind::ExternFunction => {
+                self.block.extend(&[Instruction::call_extern(symbol.id)]);
+            }
+            SymbolKind::Function => {
+                self.call_sites.insert(
+                    self.block.next_address(),
+                    (self.file, call.ident.span.clone()),
+                );
+                self.block.call_symbol(symbol.id);
+            }
+            SymbolKind::Instance | SymbolKind::Other => {
+                return Err(CompileError::NotCallable {
+                    ident: call.ident.raw.clone(),
+                    file: self.file,
+                    span: call.ident.span.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_assign(op: &AssocOp) -> bool {
+    matches!(
+        op,
+        AssocOp::Assign
+            | AssocOp::AddAssign
+            | AssocOp::SubtractAssign
+            | AssocOp::MultiplyAssign
+            | AssocOp::DivideAssign
+    )
+}
+
+fn binary_opcode(op: &AssocOp) -> Opcode {
+    match op {
+        AssocOp::Add => Opcode::Add,
+        AssocOp::Subtract => Opcode::Sub,
+        AssocOp::Multiply => Opcode::Mul,
+        AssocOp::Divide => Opcode::Div,
+        AssocOp::Equal => Opcode::Eq,
+        AssocOp::NotEqual => Opcode::Neq,
+        AssocOp::Less => Opcode::Lt,
+        AssocOp::LessEqual => Opcode::Lte,
+        AssocOp::Greater => Opcode::Gt,
+        AssocOp::GreaterEqual => Opcode::Gte,
+        AssocOp::And => Opcode::And,
+        AssocOp::Or => Opcode::Orr,
+        AssocOp::BitAnd => Opcode::AndB,
+        AssocOp::BitOr => Opcode::Or,
+        AssocOp::ShiftLeft => Opcode::Lsl,
+        AssocOp::ShiftRight => Opcode::Lsr,
+        AssocOp::Assign => unreachable!("assignment is typed, see `BlockBuilder::mov_instruction`"),
+        AssocOp::AddAssign => Opcode::AddMovI,
+        AssocOp::SubtractAssign => Opcode::SubMovI,
+        AssocOp::MultiplyAssign => Opcode::MulMovI,
+        AssocOp::DivideAssign => Opcode::DivMovI,
+    }
+}
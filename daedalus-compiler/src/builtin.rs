@@ -1,4 +1,116 @@
-pub fn get_address(v: &str) -> Option<u32> {
+use std::collections::HashMap;
+
+use dat_file::{
+    properties::{DataType, PropFlag},
+    DatFile, SymbolData,
+};
+
+use crate::game::Game;
+
+/// Extern addresses of a particular engine build, merged over the built-in table of a game
+#[derive(Debug, Default, Clone)]
+pub struct ExternMap {
+    addresses: HashMap<String, u32>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("line {line}: {reason}")]
+pub struct ExternMapError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl ExternMap {
+    /// Parse a map in the `NAME = ADDRESS` per line format, addresses are either decimal or `0x`
+    /// prefixed hex, `#` starts a comment
+    ///
+    /// ```text
+    /// # Union
+    /// Hlp_GetSteamPersonalName = 0x7a0010
+    /// ```
+    pub fn parse(src: &str) -> Result<Self, ExternMapError> {
+        let mut addresses = HashMap::new();
+
+        for (id, line) in src.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |reason| ExternMapError {
+                line: id + 1,
+                reason,
+            };
+
+            let (name, addr) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `NAME = ADDRESS`"))?;
+
+            let (name, addr) = (name.trim(), addr.trim());
+            if name.is_empty() {
+                return Err(error("missing extern name"));
+            }
+
+            let addr = match addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")) {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => addr.parse(),
+            }
+            .map_err(|_| error("invalid address"))?;
+
+            addresses.insert(name.to_uppercase(), addr);
+        }
+
+        Ok(Self { addresses })
+    }
+
+    /// Addresses of all extern functions of a DAT file compiled by the original compiler, this
+    /// is how tables of executables the compiler has no built-in one for can be obtained
+    pub fn from_dat(dat: &DatFile) -> Self {
+        let addresses = dat
+            .symbols
+            .iter()
+            .filter(|symbol| {
+                let props = &symbol.props.elem_props;
+                props.data_type() == DataType::Func && props.flags().contains(PropFlag::EXTERNAL)
+            })
+            .filter_map(|symbol| match (&symbol.name, &symbol.data) {
+                (Some(name), SymbolData::Address(addr)) => {
+                    Some((name.to_string().to_uppercase(), *addr as u32))
+                }
+                _ => None,
+            })
+            .collect();
+
+        Self { addresses }
+    }
+
+    /// Add entries of `other`, replacing the ones already present
+    pub fn extend(&mut self, other: ExternMap) {
+        self.addresses.extend(other.addresses);
+    }
+
+    /// Engine address of an extern function, entries of the map take precedence over the
+    /// built-in table
+    pub fn get_address(&self, game: Game, v: &str) -> Option<u32> {
+        self.addresses
+            .get(v)
+            .copied()
+            .or_else(|| get_address(game, v))
+    }
+}
+
+/// Engine address of an extern function, only Gothic 2 has a built-in table
+pub fn get_address(game: Game, v: &str) -> Option<u32> {
+    match game {
+        // Gothic 1 addresses have to come from an `ExternMap`, e.g. `ExternMap::from_dat` on the
+        // original `GOTHIC.DAT`
+        Game::Gothic1 => None,
+        Game::Gothic2 => gothic2_address(v),
+    }
+}
+
+/// Addresses from the Gothic 2: Night of the Raven executable
+fn gothic2_address(v: &str) -> Option<u32> {
     let addr = match v {
         "INTTOSTRING" => 0x548a80,
         "FLOATTOSTRING" => 0x548bc0,
@@ -320,3 +432,24 @@ pub fn get_address(v: &str) -> Option<u32> {
 
     Some(addr)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extern_map() {
+        let map =
+            ExternMap::parse("# patched executable\n\nIntToString = 0x100 # moved\nMY_EXTERN=42\n")
+                .unwrap();
+
+        assert_eq!(map.get_address(Game::Gothic2, "INTTOSTRING"), Some(0x100));
+        assert_eq!(map.get_address(Game::Gothic2, "MY_EXTERN"), Some(42));
+        assert_eq!(map.get_address(Game::Gothic1, "MY_EXTERN"), Some(42));
+        assert_eq!(map.get_address(Game::Gothic2, "PRINT"), Some(0x548ef0));
+        assert_eq!(map.get_address(Game::Gothic2, "UNKNOWN"), None);
+
+        let err = ExternMap::parse("A = 1\nB = 0xZZ").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: invalid address");
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// On-disk cache of parsed files, so unchanged scripts don't get re-parsed on every compilation
+///
+/// Entries are keyed by the hash of the file content alone, so renaming or reordering files in a
+/// `.src` still hits the cache. Everything past parsing is whole-program and always re-run.
+#[derive(Debug, Clone)]
+pub struct ParseCache {
+    dir: PathBuf,
+}
+
+impl ParseCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Cached AST of `source`, missing, stale and corrupted entries are all treated as a miss
+    pub fn load(&self, source: &str) -> Option<daedalus_parser::File> {
+        let bytes = fs::read(self.entry_path(source)).ok()?;
+        let (len, ast) = bytes.split_first_chunk::<8>()?;
+
+        // Guards against hash collisions of files with different length at least
+        if u64::from_le_bytes(*len) != source.len() as u64 {
+            return None;
+        }
+
+        daedalus_parser::File::decode(ast)
+    }
+
+    /// Write the AST of `source` to the cache, failures are ignored as the cache is only an
+    /// optimization
+    pub fn store(&self, source: &str, ast: &daedalus_parser::File) {
+        let mut bytes = (source.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(ast.encode());
+
+        // Write to a temporary file first, so concurrent compilations never see half of an entry
+        let path = self.entry_path(source);
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        let _ = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&tmp, bytes))
+            .and_then(|_| fs::rename(&tmp, &path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&tmp);
+            });
+    }
+
+    fn entry_path(&self, source: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.ast", content_hash(source)))
+    }
+}
+
+/// 64-bit FNV-1a, stable across runs and platforms unlike `DefaultHasher`
+fn content_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cache(name: &str) -> ParseCache {
+        let dir = std::env::temp_dir().join(format!(
+            "daedalus-parse-cache-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        ParseCache::new(dir)
+    }
+
+    fn parse(src: &str) -> daedalus_parser::File {
+        daedalus_parser::File::parse(&mut daedalus_parser::DaedalusParser {
+            lexer: &mut daedalus_parser::DaedalusLexer::new(src),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let cache = temp_cache("hit-and-miss");
+        let src = "func int f() { return 1; };";
+
+        assert!(cache.load(src).is_none());
+
+        let ast = parse(src);
+        cache.store(src, &ast);
+        let cached = cache.load(src).unwrap();
+        assert_eq!(format!("{cached:?}"), format!("{ast:?}"));
+
+        assert!(cache.load("func int f() { return 2; };").is_none());
+
+        // Corrupted entries are a miss, not an error
+        fs::write(cache.entry_path(src), b"garbage").unwrap();
+        assert!(cache.load(src).is_none());
+
+        fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn reordered_sources() {
+        let cache = temp_cache("reordered");
+        let options = crate::CompileOptions {
+            cache_dir: Some(cache.dir().to_owned()),
+            ..Default::default()
+        };
+
+        let a = ("a.d", "const int A = 1;");
+        let b = ("b.d", "func int f() { return A; };");
+        let compile = |options: &crate::CompileOptions,
+                       sources: [(&'static str, &'static str); 2]| {
+            crate::compile_sources_encoded(options, sources)
+                .unwrap()
+                .output
+        };
+
+        let uncached = compile(&Default::default(), [a, b]);
+        assert_eq!(compile(&options, [a, b]), uncached);
+        assert_eq!(compile(&options, [a, b]), uncached);
+
+        // Both ASTs come from the cache, symbols still have to follow the new order
+        let reordered = compile(&Default::default(), [b, a]);
+        assert_ne!(reordered, uncached);
+        assert_eq!(compile(&options, [b, a]), reordered);
+
+        fs::remove_dir_all(cache.dir()).unwrap();
+    }
+}
@@ -0,0 +1,521 @@
+use dat_file::properties::DataType;
+
+use crate::{dat_symbol_table, game::Game};
+
+/// Field of an engine class, as declared by the original scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub ty: DataType,
+    pub count: u32,
+}
+
+impl Field {
+    const fn new(name: &'static str, ty: DataType) -> Self {
+        Self { name, ty, count: 1 }
+    }
+
+    const fn array(self, count: u32) -> Self {
+        Self { count, ..self }
+    }
+}
+
+const fn int(name: &'static str) -> Field {
+    Field::new(name, DataType::Int)
+}
+
+const fn float(name: &'static str) -> Field {
+    Field::new(name, DataType::Float)
+}
+
+const fn string(name: &'static str) -> Field {
+    Field::new(name, DataType::String)
+}
+
+const fn func(name: &'static str) -> Field {
+    Field::new(name, DataType::Func)
+}
+
+/// How the engine accesses fields of a class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fields {
+    /// Fields are read at fixed offsets, so they have to be declared in this order
+    Fixed(&'static [Field]),
+    /// Fields are only looked up by their symbol names, so any number of them is fine, as long as
+    /// they are all of the given type
+    ByName(DataType),
+}
+
+/// Where the engine keeps fields of a script class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassLayout {
+    /// Offset of the first field from the start of the engine object
+    pub offset: i32,
+    pub fields: Fields,
+}
+
+impl ClassLayout {
+    const fn fixed(offset: i32, fields: &'static [Field]) -> Self {
+        Self {
+            offset,
+            fields: Fields::Fixed(fields),
+        }
+    }
+
+    /// Size of all fields combined, in bytes, `None` if the engine does not care about it
+    pub fn size(&self) -> Option<i32> {
+        match self.fields {
+            Fields::Fixed(fields) => Some(fields.iter().map(field_bytes).sum()),
+            Fields::ByName(_) => None,
+        }
+    }
+
+    /// Type of the field and its offset from the first field, `name` has to be uppercase
+    pub fn field(&self, name: &str) -> Option<(DataType, Option<i32>)> {
+        match self.fields {
+            Fields::Fixed(fields) => {
+                let mut offset = 0;
+                for field in fields {
+                    if field.name == name {
+                        return Some((field.ty, Some(offset)));
+                    }
+                    offset += field_bytes(field);
+                }
+                None
+            }
+            Fields::ByName(ty) => Some((ty, None)),
+        }
+    }
+}
+
+fn field_bytes(field: &Field) -> i32 {
+    let size = dat_symbol_table::field_size(field.ty).expect("engine fields have a size");
+    field.count as i32 * size
+}
+
+/// Memory layout the engine expects from a class, classes defined by scripts themselves have none
+pub fn get_layout(game: Game, class: &str) -> Option<ClassLayout> {
+    match game {
+        Game::Gothic1 => gothic1_layout(class),
+        Game::Gothic2 => gothic2_layout(class),
+    }
+}
+
+/// Classes the engine knows about, but whose layout is not in this table yet
+pub fn is_unknown_engine_class(game: Game, class: &str) -> bool {
+    let common = matches!(
+        class,
+        "C_MISSION" | "CCAMSYS" | "C_SNDSYS_CFG" | "CFX_BASE" | "C_PARTICLEFXEMITKEY"
+    );
+    match game {
+        Game::Gothic1 => common,
+        Game::Gothic2 => common || class == "C_GILVALUES",
+    }
+}
+
+/// Classes that are laid out the same way in both games
+fn common_layout(class: &str) -> Option<ClassLayout> {
+    let layout = match class {
+        "C_INFO" => ClassLayout::fixed(0, C_INFO),
+        "C_ITEMREACT" => ClassLayout::fixed(0, C_ITEMREACT),
+        "C_FOCUS" => ClassLayout::fixed(0, C_FOCUS),
+        "C_SPELL" => ClassLayout::fixed(0, C_SPELL),
+        "C_FIGHTAI" => ClassLayout::fixed(0, C_FIGHTAI),
+        "C_SFX" => ClassLayout::fixed(0, C_SFX),
+        "C_MUSICSYS_CFG" => ClassLayout::fixed(0, C_MUSICSYS_CFG),
+        "C_MUSICTHEME" => ClassLayout::fixed(0, C_MUSICTHEME),
+        "C_MUSICJINGLE" => ClassLayout::fixed(0, C_MUSICJINGLE),
+        "C_MENU" => ClassLayout::fixed(0, C_MENU),
+        // Outputs are looked up as `SVM_<voice>.<name>`
+        "C_SVM" => ClassLayout {
+            offset: 0,
+            fields: Fields::ByName(DataType::String),
+        },
+        _ => return None,
+    };
+    Some(layout)
+}
+
+/// Layouts from the Gothic 1 executable
+fn gothic1_layout(class: &str) -> Option<ClassLayout> {
+    let layout = match class {
+        // Fields of vobs start right after `zCVob`
+        "C_NPC" => ClassLayout::fixed(288, G1_C_NPC),
+        "C_ITEM" => ClassLayout::fixed(288, G1_C_ITEM),
+        "C_MENU_ITEM" => ClassLayout::fixed(0, &C_MENU_ITEM[..C_MENU_ITEM.len() - 3]),
+        "C_PARTICLEFX" => ClassLayout::fixed(0, &C_PARTICLEFX[..C_PARTICLEFX.len() - 5]),
+        _ => return common_layout(class),
+    };
+    Some(layout)
+}
+
+/// Layouts from the Gothic 2: Night of the Raven executable
+fn gothic2_layout(class: &str) -> Option<ClassLayout> {
+    let layout = match class {
+        "C_NPC" => ClassLayout::fixed(288, G2_C_NPC),
+        "C_ITEM" => ClassLayout::fixed(288, G2_C_ITEM),
+        "C_MENU_ITEM" => ClassLayout::fixed(0, C_MENU_ITEM),
+        "C_PARTICLEFX" => ClassLayout::fixed(0, C_PARTICLEFX),
+        _ => return common_layout(class),
+    };
+    Some(layout)
+}
+
+const G1_C_NPC: &[Field] = &[
+    int("ID"),
+    string("NAME").array(5),
+    string("SLOT"),
+    int("NPCTYPE"),
+    int("FLAGS"),
+    int("ATTRIBUTE").array(8),
+    int("PROTECTION").array(8),
+    int("DAMAGE").array(8),
+    int("DAMAGETYPE"),
+    int("GUILD"),
+    int("LEVEL"),
+    func("MISSION").array(5),
+    int("FIGHT_TACTIC"),
+    int("WEAPON"),
+    int("VOICE"),
+    int("VOICEPITCH"),
+    int("BODYMASS"),
+    func("DAILY_ROUTINE"),
+    func("START_AISTATE"),
+    string("SPAWNPOINT"),
+    int("SPAWNDELAY"),
+    int("SENSES"),
+    int("SENSES_RANGE"),
+    int("AIVAR").array(50),
+    string("WP"),
+    int("EXP"),
+    int("EXP_NEXT"),
+    int("LP"),
+];
+
+const G2_C_NPC: &[Field] = &[
+    int("ID"),
+    string("NAME").array(5),
+    string("SLOT"),
+    string("EFFECT"),
+    int("NPCTYPE"),
+    int("FLAGS"),
+    int("ATTRIBUTE").array(8),
+    int("HITCHANCE").array(5),
+    int("PROTECTION").array(8),
+    int("DAMAGE").array(8),
+    int("DAMAGETYPE"),
+    int("GUILD"),
+    int("LEVEL"),
+    func("MISSION").array(5),
+    int("FIGHT_TACTIC"),
+    int("WEAPON"),
+    int("VOICE"),
+    int("VOICEPITCH"),
+    int("BODYMASS"),
+    func("DAILY_ROUTINE"),
+    func("START_AISTATE"),
+    string("SPAWNPOINT"),
+    int("SPAWNDELAY"),
+    int("SENSES"),
+    int("SENSES_RANGE"),
+    int("AIVAR").array(100),
+    string("WP"),
+    int("EXP"),
+    int("EXP_NEXT"),
+    int("LP"),
+    int("BODYSTATEINTERRUPTABLEOVERRIDE"),
+    int("NOFOCUS"),
+];
+
+const G1_C_ITEM: &[Field] = &[
+    int("ID"),
+    string("NAME"),
+    string("NAMEID"),
+    int("HP"),
+    int("HP_MAX"),
+    int("MAINFLAG"),
+    int("FLAGS"),
+    int("WEIGHT"),
+    int("VALUE"),
+    int("DAMAGETYPE"),
+    int("DAMAGETOTAL"),
+    int("DAMAGE").array(8),
+    int("WEAR"),
+    int("PROTECTION").array(8),
+    int("NUTRITION"),
+    int("COND_ATR").array(3),
+    int("COND_VALUE").array(3),
+    int("CHANGE_ATR").array(3),
+    int("CHANGE_VALUE").array(3),
+    func("MAGIC"),
+    func("ON_EQUIP"),
+    func("ON_UNEQUIP"),
+    func("ON_STATE").array(4),
+    func("OWNER"),
+    int("OWNERGUILD"),
+    int("DISGUISEGUILD"),
+    string("VISUAL"),
+    string("VISUAL_CHANGE"),
+    int("VISUAL_SKIN"),
+    string("SCEMENAME"),
+    int("MATERIAL"),
+    int("MUNITION"),
+    int("SPELL"),
+    int("RANGE"),
+    int("MAG_CIRCLE"),
+    string("DESCRIPTION"),
+    string("TEXT").array(6),
+    int("COUNT").array(6),
+];
+
+const G2_C_ITEM: &[Field] = &[
+    int("ID"),
+    string("NAME"),
+    string("NAMEID"),
+    int("HP"),
+    int("HP_MAX"),
+    int("MAINFLAG"),
+    int("FLAGS"),
+    int("WEIGHT"),
+    int("VALUE"),
+    int("DAMAGETYPE"),
+    int("DAMAGETOTAL"),
+    int("DAMAGE").array(8),
+    int("WEAR"),
+    int("PROTECTION").array(8),
+    int("NUTRITION"),
+    int("COND_ATR").array(3),
+    int("COND_VALUE").array(3),
+    int("CHANGE_ATR").array(3),
+    int("CHANGE_VALUE").array(3),
+    func("MAGIC"),
+    func("ON_EQUIP"),
+    func("ON_UNEQUIP"),
+    func("ON_STATE").array(4),
+    func("OWNER"),
+    int("OWNERGUILD"),
+    int("DISGUISEGUILD"),
+    string("VISUAL"),
+    string("VISUAL_CHANGE"),
+    string("EFFECT"),
+    int("VISUAL_SKIN"),
+    string("SCEMENAME"),
+    int("MATERIAL"),
+    int("MUNITION"),
+    int("SPELL"),
+    int("RANGE"),
+    int("MAG_CIRCLE"),
+    string("DESCRIPTION"),
+    string("TEXT").array(6),
+    int("COUNT").array(6),
+    int("INV_ZBIAS"),
+    int("INV_ROTX"),
+    int("INV_ROTY"),
+    int("INV_ROTZ"),
+    int("INV_ANIMATE"),
+];
+
+const C_INFO: &[Field] = &[
+    int("NPC"),
+    int("NR"),
+    int("IMPORTANT"),
+    func("CONDITION"),
+    func("INFORMATION"),
+    string("DESCRIPTION"),
+    int("TRADE"),
+    int("PERMANENT"),
+];
+
+const C_ITEMREACT: &[Field] = &[
+    int("NPC"),
+    int("TRADE_ITEM"),
+    int("TRADE_AMOUNT"),
+    int("REQUESTED_CAT"),
+    int("REQUESTED_ITEM"),
+    int("REQUESTED_AMOUNT"),
+    func("REACTION"),
+];
+
+const C_FOCUS: &[Field] = &[
+    float("NPC_LONGRANGE"),
+    float("NPC_RANGE1"),
+    float("NPC_RANGE2"),
+    float("NPC_AZI"),
+    float("NPC_ELEVDO"),
+    float("NPC_ELEVUP"),
+    int("NPC_PRIO"),
+    float("ITEM_RANGE1"),
+    float("ITEM_RANGE2"),
+    float("ITEM_AZI"),
+    float("ITEM_ELEVDO"),
+    float("ITEM_ELEVUP"),
+    int("ITEM_PRIO"),
+    float("MOB_RANGE1"),
+    float("MOB_RANGE2"),
+    float("MOB_AZI"),
+    float("MOB_ELEVDO"),
+    float("MOB_ELEVUP"),
+    int("MOB_PRIO"),
+];
+
+const C_SPELL: &[Field] = &[
+    float("TIME_PER_MANA"),
+    int("DAMAGE_PER_LEVEL"),
+    int("DAMAGETYPE"),
+    int("SPELLTYPE"),
+    int("CANTURNDURINGINVEST"),
+    int("CANCHANGETARGETDURINGINVEST"),
+    int("ISMULTIEFFECT"),
+    int("TARGETCOLLECTALGO"),
+    int("TARGETCOLLECTTYPE"),
+    int("TARGETCOLLECTRANGE"),
+    int("TARGETCOLLECTAZI"),
+    int("TARGETCOLLECTELEV"),
+];
+
+const C_FIGHTAI: &[Field] = &[int("MOVE").array(6)];
+
+const C_SFX: &[Field] = &[
+    string("FILE"),
+    int("PITCHOFF"),
+    int("PITCHVAR"),
+    int("VOL"),
+    int("LOOP"),
+    int("LOOPSTARTOFFSET"),
+    int("LOOPENDOFFSET"),
+    float("REVERBLEVEL"),
+    string("PFXNAME"),
+];
+
+const C_MUSICSYS_CFG: &[Field] = &[
+    float("VOLUME"),
+    int("BITRESOLUTION"),
+    int("GLOBALREVERBENABLED"),
+    int("SAMPLERATE"),
+    int("NUMCHANNELS"),
+    int("REVERBBUFFERSIZE"),
+];
+
+const C_MUSICTHEME: &[Field] = &[
+    string("FILE"),
+    float("VOL"),
+    int("LOOP"),
+    float("REVERBMIX"),
+    float("REVERBTIME"),
+    int("TRANSTYPE"),
+    int("TRANSSUBTYPE"),
+];
+
+const C_MUSICJINGLE: &[Field] = &[
+    string("NAME"),
+    int("LOOP"),
+    float("VOL"),
+    int("TRANSSUBTYPE"),
+];
+
+const C_MENU: &[Field] = &[
+    string("BACKPIC"),
+    string("BACKWORLD"),
+    int("POSX"),
+    int("POSY"),
+    int("DIMX"),
+    int("DIMY"),
+    int("ALPHA"),
+    string("MUSICTHEME"),
+    int("EVENTTIMERMSEC"),
+    string("ITEMS").array(150),
+    int("FLAGS"),
+    int("DEFAULTOUTGAME"),
+    int("DEFAULTINGAME"),
+];
+
+/// The last 3 fields are Gothic 2 only
+const C_MENU_ITEM: &[Field] = &[
+    string("FONTNAME"),
+    string("TEXT").array(10),
+    string("BACKPIC"),
+    string("ALPHAMODE"),
+    int("ALPHA"),
+    int("TYPE"),
+    int("ONSELACTION").array(5),
+    string("ONSELACTION_S").array(5),
+    string("ONCHGSETOPTION"),
+    string("ONCHGSETOPTIONSECTION"),
+    func("ONEVENTACTION").array(10),
+    int("POSX"),
+    int("POSY"),
+    int("DIMX"),
+    int("DIMY"),
+    float("SIZESTARTSCALE"),
+    int("FLAGS"),
+    float("OPENDELAYTIME"),
+    float("OPENDURATION"),
+    float("USERFLOAT").array(4),
+    string("USERSTRING").array(4),
+    int("FRAMESIZEX"),
+    int("FRAMESIZEY"),
+    string("HIDEIFOPTIONSECTIONSET"),
+    string("HIDEIFOPTIONSET"),
+    int("HIDEONVALUE"),
+];
+
+/// The last 5 fields are Gothic 2 only
+const C_PARTICLEFX: &[Field] = &[
+    float("PPSVALUE"),
+    string("PPSSCALEKEYS_S"),
+    int("PPSISLOOPING"),
+    int("PPSISSMOOTH"),
+    float("PPSFPS"),
+    string("PPSCREATEEM_S"),
+    float("PPSCREATEEMDELAY"),
+    string("SHPTYPE_S"),
+    string("SHPFOR_S"),
+    string("SHPOFFSETVEC_S"),
+    string("SHPDISTRIBTYPE_S"),
+    float("SHPDISTRIBWALKSPEED"),
+    int("SHPISVOLUME"),
+    string("SHPDIM_S"),
+    string("SHPMESH_S"),
+    int("SHPMESHRENDER_B"),
+    string("SHPSCALEKEYS_S"),
+    int("SHPSCALEISLOOPING"),
+    int("SHPSCALEISSMOOTH"),
+    float("SHPSCALEFPS"),
+    string("DIRMODE_S"),
+    string("DIRFOR_S"),
+    string("DIRMODETARGETFOR_S"),
+    string("DIRMODETARGETPOS_S"),
+    float("DIRANGLEHEAD"),
+    float("DIRANGLEHEADVAR"),
+    float("DIRANGLEELEV"),
+    float("DIRANGLEELEVVAR"),
+    float("VELAVG"),
+    float("VELVAR"),
+    float("LSPPARTAVG"),
+    float("LSPPARTVAR"),
+    string("FLYGRAVITY_S"),
+    int("FLYCOLLDET_B"),
+    string("VISNAME_S"),
+    string("VISORIENTATION_S"),
+    int("VISTEXISQUADPOLY"),
+    float("VISTEXANIFPS"),
+    int("VISTEXANIISLOOPING"),
+    string("VISTEXCOLORSTART_S"),
+    string("VISTEXCOLOREND_S"),
+    string("VISSIZESTART_S"),
+    float("VISSIZEENDSCALE"),
+    string("VISALPHAFUNC_S"),
+    float("VISALPHASTART"),
+    float("VISALPHAEND"),
+    float("TRLFADESPEED"),
+    string("TRLTEXTURE_S"),
+    float("TRLWIDTH"),
+    float("MRKFADESPEED"),
+    string("MRKTEXTURE_S"),
+    float("MRKSIZE"),
+    string("FLOCKMODE"),
+    float("FLOCKSTRENGTH"),
+    int("USEEMITTERSFOR"),
+    string("TIMESTARTEND_S"),
+    int("M_BISAMBIENTPFX"),
+];
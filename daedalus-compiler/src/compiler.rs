@@ -0,0 +1,1320 @@
+use std::collections::HashMap;
+
+use daedalus_bytecode::Bytecode;
+use daedalus_parser::{Class, ExprKind, LitKind, Ty, Var, VarKind};
+use dat_file::properties::{DataType, SymbolCodeSpan};
+use logos::Span;
+use zstring::ZString;
+
+use crate::{
+    block_builder::BlockBuilder,
+    builtin::ExternMap,
+    class_layout::{self, ClassLayout},
+    const_eval::{ConstValues, Value},
+    dat_symbol_table::{self, DatSymbolTable},
+    error::{CompileError, CompileWarning},
+    files::{File, FileId, Files},
+    game::Game,
+    symbol_indices::{self, SymbolIndex, SymbolIndices},
+    CompileOptions,
+};
+
+pub struct Compiler {
+    game: Game,
+    externs: ExternMap,
+    symbol_indices: SymbolIndices,
+    const_values: ConstValues,
+    symbol_table: DatSymbolTable,
+    bytecode: Bytecode,
+    /// Source of every `Call` instruction, by its address, for errors of unresolved calls
+    call_sites: HashMap<u32, (FileId, Span)>,
+    errors: Vec<CompileError>,
+    warnings: Vec<CompileWarning>,
+}
+
+impl Compiler {
+    pub fn new(
+        options: &CompileOptions,
+        symbol_indices: SymbolIndices,
+        const_values: ConstValues,
+    ) -> Self {
+        Self {
+            game: options.game,
+            externs: options.externs.clone(),
+            symbol_table: DatSymbolTable::new(&symbol_indices)
+                .with_string_pooling(options.pool_strings),
+            symbol_indices,
+            const_values,
+            bytecode: Bytecode::new(),
+            call_sites: HashMap::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn handle_item(
+        &mut self,
+        files: &Files,
+        file_id: FileId,
+        item: &daedalus_parser::Item,
+    ) -> Result<(), CompileError> {
+        match item {
+            daedalus_parser::Item::ExternFunc(func) => {
+                let name = ZString::from(func.ident.raw.as_bytes().to_ascii_uppercase());
+                let ty = self.resolve_type(file_id, &func.ty)?;
+
+                let args = func
+                    .args
+                    .iter()
+                    .map(|var| {
+                        let ident = ZString::from(var.ident.raw.as_bytes());
+                        let ty = self.resolve_type(file_id, &var.ty)?;
+
+                        Ok((ident, ty, SymbolCodeSpan::empty(file_id.raw())))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let addr = self
+                    .externs
+                    .get_address(self.game, &func.ident.raw.to_uppercase())
+                    .ok_or_else(|| CompileError::UnknownExtern {
+                        ident: func.ident.raw.clone(),
+                        game: self.game,
+                        file: file_id,
+                        span: func.ident.span.clone(),
+                    })? as i32;
+
+                self.symbol_table.extern_func(
+                    name,
+                    SymbolCodeSpan::empty(file_id.raw()),
+                    &args,
+                    ty,
+                    addr,
+                );
+            }
+
+            daedalus_parser::Item::Class(class) => {
+                let name = ZString::from(class.ident.raw.as_bytes().to_ascii_uppercase());
+                let span = &class.span;
+
+                let line_start = files.line_index(file_id, span.start as u32).0;
+                let line_count = files.line_index(file_id, span.end as u32).0 - line_start;
+
+                let span = SymbolCodeSpan::new(
+                    file_id.raw(),
+                    (line_start + 1, line_count + 1),
+                    (span.start as u32, span.end as u32 - span.start as u32 + 2),
+                );
+
+                let fields = class
+                    .fields
+                    .iter()
+                    .map(|var| {
+                        let ident = ZString::from(var.ident.raw.as_bytes().to_ascii_uppercase());
+                        let ty = self.resolve_type(file_id, &var.ty)?;
+
+                        if dat_symbol_table::field_size(ty).is_none() {
+                            return Err(CompileError::Unsupported {
+                                what: "class field of instance type",
+                                file: file_id,
+                                span: var.ty.span.clone(),
+                            });
+                        }
+
+                        // Codespans produced by Zengin are either hard for me to understand, or straight
+                        // up broken, so let's make compatibility with them an optional feature
+                        let span = if files.code_span_compat() {
+                            let mut span = var.span.clone();
+
+                            let line_start = files.line_index(file_id, span.start as u32).0;
+                            let line_count =
+                                files.line_index(file_id, span.end as u32).0 - line_start;
+
+                            // Don't ask me why field span starts at the beginning of the line, this
+                            // is straight up broken, if 2 fields are on the same line, but that's
+                            // what zengine does...
+                            span.start =
+                                files.line_span(file_id, line_start).unwrap().start().0 as usize;
+
+                            // Don't ask me why we add +3 to char_count of a span, we just do as
+                            // that makes it compatible with zengin for some reason
+                            SymbolCodeSpan::new(
+                                file_id.raw(),
+                                (line_start + 1, line_count + 1),
+                                (span.start as u32, span.end as u32 - span.start as u32 + 3),
+                            )
+                        } else {
+                            // Path for sane spans without compatibility with zengin ones
+
+                            let span = &var.span;
+                            let line_start = files.line_index(file_id, span.start as u32).0;
+                            let line_count =
+                                files.line_index(file_id, span.end as u32).0 - line_start;
+
+                            SymbolCodeSpan::new(
+                                file_id.raw(),
+                                (line_start + 1, line_count + 1),
+                                (span.start as u32, span.end as u32 - span.start as u32),
+                            )
+                        };
+
+                        let count = self.var_count(file_id, var)?;
+
+                        Ok((ident, ty, count, span))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let size = dat_symbol_table::class_size(&fields);
+
+                let class_name = class.ident.raw.to_uppercase();
+                let offset = match class_layout::get_layout(self.game, &class_name) {
+                    Some(layout) => {
+                        self.check_class_layout(file_id, class, &fields, size, &layout);
+                        layout.offset
+                    }
+                    None => {
+                        if class_layout::is_unknown_engine_class(self.game, &class_name) {
+                            self.warnings.push(CompileWarning::UnknownClassLayout {
+                                class: class.ident.raw.clone(),
+                                game: self.game,
+                                file: file_id,
+                                span: class.ident.span.clone(),
+                            });
+                        }
+                        0
+                    }
+                };
+
+                self.symbol_table.class(name, span, &fields, size, offset);
+            }
+
+            daedalus_parser::Item::Instance(instance) => {
+                let ident = ZString::from(instance.ident.raw.as_bytes().to_ascii_uppercase());
+                let parent_name = instance.parent.raw.to_uppercase();
+                let parent = self.resolve_ident(file_id, &instance.parent)?;
+                let scope = self.symbol_indices.parent_class(&parent_name);
+                let span = &instance.span;
+
+                let line_start = files.line_index(file_id, span.start as u32).0;
+                let line_count = files.line_index(file_id, span.end as u32).0 - line_start;
+
+                let span = SymbolCodeSpan::new(
+                    file_id.raw(),
+                    (line_start + 1, line_count + 1),
+                    (span.start as u32, span.end as u32 - span.start as u32 + 2),
+                );
+
+                let address = self.bytecode.next_available_address();
+
+                let this = self.symbol_table.instance(ident, span, address, parent.id);
+
+                let mut block = self.bytecode.block_builder();
+
+                // Prototype sets up the defaults, the instance body only overrides them
+                if parent.ty == DataType::Prototype {
+                    self.call_sites.insert(
+                        block.next_address(),
+                        (file_id, instance.parent.span.clone()),
+                    );
+                    block.call_symbol(parent.id);
+                }
+
+                let mut builder = BlockBuilder {
+                    file: file_id,
+                    scope,
+                    this: Some(this),
+                    prototype: false,
+                    symbol_indices: &self.symbol_indices,
+                    const_values: &self.const_values,
+                    symbol_table: &mut self.symbol_table,
+                    block: &mut block,
+                    call_sites: &mut self.call_sites,
+                    errors: &mut self.errors,
+                };
+
+                // attribute[0] = 20
+                // attribute[1] = 40
+                // Mdl_SetVisual(self, "HUMANS.MDS")
+                // Mdl_SetVisualBody(self, "hum_body_Naked0", 9, 0, "Hum_Head_Pony", 18, 0, -1);
+                builder.visit_block(&instance.block);
+
+                block.ret();
+            }
+
+            daedalus_parser::Item::Prototype(prototype) => {
+                let ident = ZString::from(prototype.ident.raw.as_bytes().to_ascii_uppercase());
+                let class = prototype.parent.raw.to_uppercase();
+                let class_id = self.resolve_ident(file_id, &prototype.parent)?.id;
+                let span = &prototype.span;
+
+                let line_start = files.line_index(file_id, span.start as u32).0;
+                let line_count = files.line_index(file_id, span.end as u32).0 - line_start;
+
+                let span = SymbolCodeSpan::new(
+                    file_id.raw(),
+                    (line_start + 1, line_count + 1),
+                    (span.start as u32, span.end as u32 - span.start as u32 + 2),
+                );
+
+                let address = self.bytecode.next_available_address();
+
+                let this = self.symbol_table.prototype(ident, span, address, class_id);
+
+                let mut block = self.bytecode.block_builder();
+
+                let mut builder = BlockBuilder {
+                    file: file_id,
+                    scope: &class,
+                    this: Some(this),
+                    prototype: true,
+                    symbol_indices: &self.symbol_indices,
+                    const_values: &self.const_values,
+                    symbol_table: &mut self.symbol_table,
+                    block: &mut block,
+                    call_sites: &mut self.call_sites,
+                    errors: &mut self.errors,
+                };
+
+                builder.visit_block(&prototype.block);
+
+                block.ret();
+            }
+
+            daedalus_parser::Item::Func(func) => {
+                let scope = func.ident.raw.to_uppercase();
+                let ident = ZString::from(scope.as_bytes());
+                let span = &func.span;
+
+                let line_start = files.line_index(file_id, span.start as u32).0;
+                let line_count = files.line_index(file_id, span.end as u32).0 - line_start;
+
+                let span = SymbolCodeSpan::new(
+                    file_id.raw(),
+                    (line_start + 1, line_count + 1),
+                    (span.start as u32, span.end as u32 - span.start as u32 + 2),
+                );
+
+                let args = func
+                    .args
+                    .iter()
+                    .map(|var| {
+                        let ident = ZString::from(var.ident.raw.as_bytes().to_ascii_uppercase());
+                        let ty = self.resolve_type(file_id, &var.ty)?;
+
+                        Ok((ident, ty, var_code_span(files, file_id, var)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let ty = self.resolve_type(file_id, &func.ty)?;
+                let address = self.bytecode.next_available_address();
+
+                self.symbol_table
+                    .func(ident.clone(), span, &args, ty, address);
+
+                for var in symbol_indices::block_locals(&func.block) {
+                    let name = format!("{scope}.{}", var.ident.raw.to_uppercase());
+                    let name = ZString::from(name.into_bytes());
+
+                    let ty = self.resolve_type(file_id, &var.ty)?;
+                    let count = self.var_count(file_id, var)?;
+
+                    self.symbol_table
+                        .var(name, var_code_span(files, file_id, var), ty, count);
+                }
+
+                let mut block = self.bytecode.block_builder();
+
+                let mut builder = BlockBuilder {
+                    file: file_id,
+                    scope: &scope,
+                    this: None,
+                    prototype: false,
+                    symbol_indices: &self.symbol_indices,
+                    const_values: &self.const_values,
+                    symbol_table: &mut self.symbol_table,
+                    block: &mut block,
+                    call_sites: &mut self.call_sites,
+                    errors: &mut self.errors,
+                };
+
+                builder.pop_args(&func.args)?;
+                builder.visit_block(&func.block);
+
+                block.ret();
+            }
+            daedalus_parser::Item::Const(item) => {
+                let name = ZString::from(item.ident.raw.as_bytes().to_ascii_uppercase());
+
+                let span = &item.span;
+
+                let line_start = files.line_index(file_id, span.start as u32).0;
+                let line_count = files.line_index(file_id, span.end as u32).0 - line_start;
+
+                let span = SymbolCodeSpan::new(
+                    file_id.raw(),
+                    (line_start + 1, line_count + 1),
+                    (span.start as u32, span.end as u32 - span.start as u32 + 3),
+                );
+
+                let Some(value) = self.const_values.map.get(&item.ident.raw.to_uppercase()) else {
+                    // Evaluation failed, the error has already been reported
+                    return Ok(());
+                };
+
+                self.symbol_table.const_item(name, span, value);
+            }
+            daedalus_parser::Item::Var(var) => {
+                let name = ZString::from(var.ident.raw.as_bytes().to_ascii_uppercase());
+
+                // Zengin does not run any code for globals, so there is nowhere to put the
+                // initializer
+                if matches!(
+                    var.kind,
+                    VarKind::Value { init: Some(_) } | VarKind::Array { init: Some(_), .. }
+                ) {
+                    return Err(CompileError::Unsupported {
+                        what: "initializer of a global variable",
+                        file: file_id,
+                        span: var.span.clone(),
+                    });
+                }
+
+                let ty = self.resolve_type(file_id, &var.ty)?;
+                let count = self.var_count(file_id, var)?;
+
+                self.symbol_table
+                    .var(name, var_code_span(files, file_id, var), ty, count);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of elements of a variable, 1 for non-array ones
+    fn var_count(&self, file_id: FileId, var: &Var) -> Result<u32, CompileError> {
+        let VarKind::Array { size_init, .. } = &var.kind else {
+            return Ok(1);
+        };
+
+        let size = match &size_init.kind {
+            ExprKind::Lit(lit) => match &lit.kind {
+                LitKind::Intager(v) => Some(*v),
+                _ => None,
+            },
+            ExprKind::Ident(ident) => match self.const_values.map.get(&ident.raw.to_uppercase()) {
+                Some(Value::Int(v)) => Some(*v),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        size.and_then(|size| u32::try_from(size).ok())
+            .filter(|size| *size > 0)
+            .ok_or_else(|| CompileError::InvalidArraySize {
+                file: file_id,
+                span: size_init.span.clone(),
+            })
+    }
+
+    /// Warns about fields the engine would read from a different place than the one they are
+    /// declared at
+    fn check_class_layout(
+        &mut self,
+        file_id: FileId,
+        class: &Class,
+        fields: &[(ZString, DataType, u32, SymbolCodeSpan)],
+        size: i32,
+        layout: &ClassLayout,
+    ) {
+        if let Some(expected) = layout.size().filter(|expected| *expected != size) {
+            self.warnings.push(CompileWarning::ClassLayoutMismatch {
+                class: class.ident.raw.clone(),
+                expected,
+                found: size,
+                file: file_id,
+                span: class.ident.span.clone(),
+            });
+        }
+
+        let mut offset = 0;
+        for (var, field) in class.fields.iter().zip(fields) {
+            let found = offset;
+            offset += dat_symbol_table::class_size(std::slice::from_ref(field));
+
+            let (_, ty, _, _) = field;
+            let name = format!("{}.{}", class.ident.raw, var.ident.raw);
+
+            let warning = match layout.field(&var.ident.raw.to_uppercase()) {
+                None => CompileWarning::UnknownClassField {
+                    field: name,
+                    file: file_id,
+                    span: var.ident.span.clone(),
+                },
+                Some((expected, _)) if expected != *ty => CompileWarning::FieldTypeMismatch {
+                    field: name,
+                    expected,
+                    found: *ty,
+                    file: file_id,
+                    span: var.ty.span.clone(),
+                },
+                Some((_, Some(expected))) if expected != found => {
+                    CompileWarning::FieldOffsetMismatch {
+                        field: name,
+                        expected,
+                        found,
+                        file: file_id,
+                        span: var.ident.span.clone(),
+                    }
+                }
+                Some(_) => continue,
+            };
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Builtin type, or name of a class
+    fn resolve_type(&self, file_id: FileId, ty: &Ty) -> Result<DataType, CompileError> {
+        let data_type = symbol_indices::data_type(&ty.raw);
+
+        let is_class = || {
+            self.symbol_indices
+                .get(&ty.raw.to_uppercase())
+                .is_some_and(|symbol| symbol.ty == DataType::Class)
+        };
+
+        if data_type == DataType::Instance
+            && !ty.raw.eq_ignore_ascii_case("instance")
+            && !is_class()
+        {
+            return Err(CompileError::UnknownType {
+                ty: ty.raw.clone(),
+                file: file_id,
+                span: ty.span.clone(),
+            });
+        }
+
+        Ok(data_type)
+    }
+
+    fn resolve_ident(
+        &self,
+        file_id: FileId,
+        ident: &daedalus_parser::Ident,
+    ) -> Result<SymbolIndex, CompileError> {
+        self.symbol_indices
+            .get(&ident.raw.to_uppercase())
+            .copied()
+            .ok_or_else(|| CompileError::UnknownIdent {
+                ident: ident.raw.clone(),
+                file: file_id,
+                span: ident.span.clone(),
+            })
+    }
+
+    pub fn build(
+        mut self,
+        files: &[File],
+        span_files: &Files,
+        warnings: &mut Vec<CompileWarning>,
+    ) -> Result<Vec<u8>, Vec<CompileError>> {
+        for File { id, ast } in files.iter() {
+            for item in ast.items.iter() {
+                if let Err(err) = self.handle_item(span_files, *id, item) {
+                    self.errors.push(err);
+                }
+            }
+        }
+
+        warnings.append(&mut self.warnings);
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        let symbol_table = &self.symbol_table;
+        if let Err(err) = self.bytecode.finalize(|id| symbol_table.func_address(id)) {
+            // Jumps are only emitted by the compiler itself, so there is nothing better to point at
+            let (file, span) = match self.call_sites.get(&err.address()) {
+                Some(site) => site.clone(),
+                None => (files[0].id, 0..0),
+            };
+            return Err(vec![CompileError::Relocation { err, file, span }]);
+        }
+
+        let mut out = Vec::new();
+        self.symbol_table.encode(&mut out);
+        self.bytecode.encode(&mut out).unwrap();
+        Ok(out)
+    }
+}
+
+fn var_code_span(files: &Files, file_id: FileId, var: &Var) -> SymbolCodeSpan {
+    let span = &var.span;
+    let line_start = files.line_index(file_id, span.start as u32).0;
+    let line_count = files.line_index(file_id, span.end as u32).0 - line_start;
+
+    SymbolCodeSpan::new(
+        file_id.raw(),
+        (line_start + 1, line_count + 1),
+        (span.start as u32, span.end as u32 - span.start as u32),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::compile;
+    use daedalus_bytecode::{Instruction, InstructionData, Opcode};
+    use dat_file::{properties::PropFlag, SymbolData};
+    use indoc::indoc;
+
+    #[test]
+    fn func_body() {
+        let dat = compile(indoc! {"
+        func int add(var int a, var string b) {
+            var int c;
+            c = a;
+            return c;
+        };
+        "});
+
+        let names: Vec<_> = dat
+            .symbols
+            .iter()
+            .map(|s| s.name.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["$INSTANCE_HELP", "ADD", "ADD.A", "ADD.B", "ADD.C"]);
+
+        let func = &dat.symbols[1];
+        assert_eq!(func.props.elem_props.count(), 2);
+        assert_eq!(func.props.off_cls_ret, DataType::Int as i32);
+        assert!(func.props.elem_props.flags().contains(PropFlag::RETURN));
+
+        let instructions: Vec<_> = dat.bytecode.instructions().collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::push_var(3),
+                Instruction::mov_string(),
+                Instruction::push_var(2),
+                Instruction::mov_int(),
+                Instruction::push_var(2),
+                Instruction::push_var(4),
+                Instruction::mov_int(),
+                Instruction::push_var(4),
+                Instruction::ret(),
+                Instruction::ret(),
+            ]
+        );
+    }
+
+    #[test]
+    fn if_chain() {
+        let dat = compile(indoc! {"
+        func void check(var int a, var int b) {
+            if a {
+                a = 1;
+            } else if b {
+                a = 2;
+            } else {
+                a = 3;
+            };
+            if b {
+                b = 4;
+            };
+        };
+        "});
+
+        let instructions: Vec<_> = dat.bytecode.instructions().collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::push_var(3),
+                Instruction::mov_int(),
+                Instruction::push_var(2),
+                Instruction::mov_int(),
+                // 12
+                Instruction::push_var(2),
+                Instruction::jump_if_zero(38),
+                Instruction::push_int(1),
+                Instruction::push_var(2),
+                Instruction::mov_int(),
+                Instruction::jump(75),
+                // 38
+                Instruction::push_var(3),
+                Instruction::jump_if_zero(64),
+                Instruction::push_int(2),
+                Instruction::push_var(2),
+                Instruction::mov_int(),
+                Instruction::jump(75),
+                // 64
+                Instruction::push_int(3),
+                Instruction::push_var(2),
+                Instruction::mov_int(),
+                // 75
+                Instruction::push_var(3),
+                Instruction::jump_if_zero(96),
+                Instruction::push_int(4),
+                Instruction::push_var(3),
+                Instruction::mov_int(),
+                // 96
+                Instruction::ret(),
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_call() {
+        let dat = compile(indoc! {"
+        func void first() {
+            second();
+        };
+        func void second() {};
+        "});
+
+        let instructions: Vec<_> = dat.bytecode.instructions().collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::call(6),
+                Instruction::ret(),
+                // 6
+                Instruction::ret(),
+            ]
+        );
+        assert_eq!(dat.symbols[2].data, SymbolData::Address(6));
+    }
+
+    #[test]
+    fn expressions() {
+        let dat = compile(indoc! {"
+        const int IDX = 2;
+        func int calc(var int a, var int b) {
+            var int c[3];
+            c[IDX] = a + b * 2;
+            c -= !a;
+            return -c[IDX] >= (a || b);
+        };
+        "});
+
+        // $INSTANCE_HELP, IDX, CALC, CALC.A, CALC.B, CALC.C
+        let (a, b, c) = (3, 4, 5);
+
+        let instructions: Vec<_> = dat.bytecode.instructions().skip(4).collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::push_int(2),
+                Instruction::push_var(b),
+                Instruction::operator(Opcode::Mul),
+                Instruction::push_var(a),
+                Instruction::operator(Opcode::Add),
+                Instruction::push_var_array(c, 2),
+                Instruction::mov_int(),
+                Instruction::push_var(a),
+                Instruction::operator(Opcode::Not),
+                Instruction::push_var(c),
+                Instruction::operator(Opcode::SubMovI),
+                Instruction::push_var(b),
+                Instruction::push_var(a),
+                Instruction::operator(Opcode::Orr),
+                Instruction::push_var_array(c, 2),
+                Instruction::operator(Opcode::Negate),
+                Instruction::operator(Opcode::Gte),
+                Instruction::ret(),
+                Instruction::ret(),
+            ]
+        );
+    }
+
+    #[test]
+    fn typed_assign() {
+        let dat = compile(indoc! {"
+        class C_NPC { var int id; };
+        instance hero(C_NPC) {};
+        func void routine() {};
+        func void assign(var int i, var float f, var string s, var func fn, var C_NPC npc) {
+            i = 1;
+            f = 1.5;
+            s = \"abc\";
+            fn = routine;
+            npc = hero;
+        };
+        "});
+
+        // $INSTANCE_HELP, C_NPC, C_NPC.ID, HERO, ROUTINE, ASSIGN, ASSIGN.I, ASSIGN.F, ASSIGN.S,
+        // ASSIGN.FN, ASSIGN.NPC
+        let (hero, routine) = (3, 4);
+        let (i, f, s, func, npc) = (6, 7, 8, 9, 10);
+        // Autogenerated symbols come last
+        let abc = 11;
+
+        let instructions: Vec<_> = dat.bytecode.instructions().skip(12).collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::push_int(1),
+                Instruction::push_var(i),
+                Instruction::mov_int(),
+                Instruction::push_int(i32::from_le_bytes(1.5f32.to_le_bytes())),
+                Instruction::push_var(f),
+                Instruction::mov_float(),
+                Instruction::push_var(abc),
+                Instruction::push_var(s),
+                Instruction::mov_string(),
+                Instruction::push_int(routine),
+                Instruction::push_var(func),
+                Instruction::mov_func(),
+                Instruction::push_var_instance(hero),
+                Instruction::push_var_instance(npc),
+                Instruction::mov_instance(),
+                Instruction::ret(),
+            ]
+        );
+    }
+
+    #[test]
+    fn int_literals_as_floats() {
+        let dat = compile(indoc! {"
+        extern func void Mdl_SetModelFatness(var int npc, var float fatness)
+        func float fatness(var float f) {
+            f = 2;
+            Mdl_SetModelFatness(0, -1);
+            return 3;
+        };
+        const float SCALE = 4;
+        "});
+
+        // $INSTANCE_HELP, MDL_SETMODELFATNESS, MDL_SETMODELFATNESS.NPC,
+        // MDL_SETMODELFATNESS.FATNESS, FATNESS, FATNESS.F
+        let (extern_fn, f) = (1, 5);
+        let float = |v: f32| Instruction::push_int(v.to_bits() as i32);
+
+        let instructions: Vec<_> = dat.bytecode.instructions().skip(2).collect();
+        assert_eq!(
+            instructions,
+            [
+                float(2.0),
+                Instruction::push_var(f),
+                Instruction::mov_float(),
+                Instruction::push_int(0),
+                float(-1.0),
+                Instruction::call_extern(extern_fn),
+                float(3.0),
+                Instruction::ret(),
+                Instruction::ret(),
+            ]
+        );
+
+        assert_eq!(dat.symbols[6].data, SymbolData::Float(vec![4.0]));
+    }
+
+    #[test]
+    fn prototype_chain() {
+        let dat = compile(indoc! {"
+        class C_NPC { var int id; var int level; };
+        prototype Npc_Default(C_NPC) { level = 5; };
+        instance hero(Npc_Default) { id = 1; };
+        "});
+
+        // $INSTANCE_HELP, C_NPC, C_NPC.ID, C_NPC.LEVEL, NPC_DEFAULT, HERO
+        let (class, id, level, prototype, hero) = (1, 2, 3, 4, 5);
+
+        let prototype_symbol = &dat.symbols[prototype as usize];
+        assert_eq!(
+            prototype_symbol.props.elem_props.data_type(),
+            DataType::Prototype
+        );
+        assert_eq!(prototype_symbol.parent, Some(class));
+        assert_eq!(prototype_symbol.data, SymbolData::Address(0));
+
+        let hero_symbol = &dat.symbols[hero];
+        assert_eq!(hero_symbol.parent, Some(prototype));
+        assert_eq!(hero_symbol.data, SymbolData::Address(12));
+
+        let instructions: Vec<_> = dat.bytecode.instructions().collect();
+        assert_eq!(
+            instructions,
+            [
+                // 0
+                Instruction::push_int(5),
+                Instruction::push_var(level),
+                Instruction::mov_int(),
+                Instruction::ret(),
+                // 12
+                Instruction::call(0),
+                Instruction::push_int(1),
+                Instruction::push_var(id),
+                Instruction::mov_int(),
+                Instruction::ret(),
+            ]
+        );
+    }
+
+    #[test]
+    fn dialog_instance() {
+        let dat = compile(indoc! {r#"
+        class C_INFO {
+            var int npc;
+            var int nr;
+            var func condition;
+            var func information;
+            var string description;
+        };
+        instance hero(C_INFO) {};
+        func int DIA_Hero_Condition() { return 1; };
+        instance DIA_Hero(C_INFO) {
+            npc = hero;
+            nr = 2 + 1;
+            self.condition = DIA_Hero_Condition;
+            information = DIA_Hero_Condition;
+            self.description = "Hi";
+        };
+        "#});
+
+        // $INSTANCE_HELP, C_INFO, NPC, NR, CONDITION, INFORMATION, DESCRIPTION, HERO,
+        // DIA_HERO_CONDITION, DIA_HERO, "Hi"
+        let (npc, nr, condition, information, description) = (2, 3, 4, 5, 6);
+        let (hero, dia_condition, hi) = (7, 8, 10);
+
+        // Body of `hero` is skipped
+        let instructions: Vec<_> = dat.bytecode.instructions().skip(1).collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::push_int(1),
+                Instruction::ret(),
+                Instruction::ret(),
+                Instruction::push_var_instance(hero),
+                Instruction::push_var(npc),
+                Instruction::mov_int(),
+                Instruction::push_int(1),
+                Instruction::push_int(2),
+                Instruction::operator(Opcode::Add),
+                Instruction::push_var(nr),
+                Instruction::mov_int(),
+                Instruction::push_int(dia_condition),
+                Instruction::push_var(condition),
+                Instruction::mov_func(),
+                Instruction::push_int(dia_condition),
+                Instruction::push_var(information),
+                Instruction::mov_func(),
+                Instruction::push_var(hi),
+                Instruction::push_var(description),
+                Instruction::mov_string(),
+                Instruction::ret(),
+            ]
+        );
+    }
+
+    #[test]
+    fn instance_fields() {
+        let dat = compile(indoc! {"
+        class C_NPC { var int guild; var int attribute[2]; };
+        var C_NPC self;
+        var C_NPC other;
+        instance hero(C_NPC) {};
+        func void f() {
+            self.attribute[1] = other.guild;
+            hero.guild += 1;
+        };
+        "});
+
+        // $INSTANCE_HELP, C_NPC, C_NPC.GUILD, C_NPC.ATTRIBUTE, SELF, OTHER, HERO, F
+        let (guild, attribute, this, other, hero) = (2, 3, 4, 5, 6);
+
+        // Body of `hero` is skipped
+        let instructions: Vec<_> = dat.bytecode.instructions().skip(1).collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::set_instance(other),
+                Instruction::push_var(guild),
+                Instruction::set_instance(this),
+                Instruction::push_var_array(attribute, 1),
+                Instruction::mov_int(),
+                Instruction::push_int(1),
+                Instruction::set_instance(hero),
+                Instruction::push_var(guild),
+                Instruction::operator(Opcode::AddMovI),
+                Instruction::ret(),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_instance_in_instance_body() {
+        let dat = compile(indoc! {"
+        class C_NPC { var int guild; var int id; };
+        var C_NPC other;
+        instance hero(C_NPC) {
+            guild = other.guild;
+            id = 1;
+        };
+        "});
+
+        // $INSTANCE_HELP, C_NPC, C_NPC.GUILD, C_NPC.ID, OTHER, HERO
+        let (guild, id, other, hero) = (2, 3, 4, 5);
+
+        let instructions: Vec<_> = dat.bytecode.instructions().collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::set_instance(other),
+                Instruction::push_var(guild),
+                Instruction::set_instance(hero),
+                Instruction::push_var(guild),
+                Instruction::mov_int(),
+                Instruction::push_int(1),
+                Instruction::push_var(id),
+                Instruction::mov_int(),
+                Instruction::ret(),
+            ]
+        );
+
+        // A prototype can't be made current again
+        let src = indoc! {"
+        class C_NPC { var int guild; };
+        var C_NPC other;
+        prototype P(C_NPC) { guild = other.guild; };
+        "};
+        let diagnostics =
+            crate::compile_sources(&CompileOptions::default(), [("test.d", src)]).unwrap_err();
+        assert!(matches!(
+            diagnostics.errors[..],
+            [CompileError::Unsupported { .. }]
+        ));
+    }
+
+    #[test]
+    fn string_pooling() {
+        let src = indoc! {r#"
+        func void print(var string s) {};
+        func void f() {
+            print("a");
+            print("b");
+            print("a");
+        };
+        "#};
+
+        let strings = |pool_strings| {
+            let options = CompileOptions {
+                pool_strings,
+                ..Default::default()
+            };
+            let dat = crate::compile_sources(&options, [("test.d", src)])
+                .unwrap()
+                .output;
+
+            // $INSTANCE_HELP, PRINT, PRINT.S, F
+            let names: Vec<_> = dat.symbols[4..]
+                .iter()
+                .map(|s| s.name.clone().unwrap())
+                .collect();
+            let pushed: Vec<_> = dat
+                .bytecode
+                .instructions()
+                .filter(|i| i.opcode == Opcode::PushVar)
+                .skip(1)
+                .map(|i| i.data)
+                .collect();
+            (names, pushed)
+        };
+
+        let (names, pushed) = strings(false);
+        assert_eq!(
+            names,
+            [b"\xFF10000", b"\xFF10001", b"\xFF10002"].map(ZString::from)
+        );
+        assert_eq!(pushed, [4, 5, 6].map(InstructionData::Symbol));
+
+        let (names, pushed) = strings(true);
+        assert_eq!(names, [b"\xFF10000", b"\xFF10001"].map(ZString::from));
+        assert_eq!(pushed, [4, 5, 4].map(InstructionData::Symbol));
+    }
+
+    #[test]
+    fn global_vars() {
+        let dat = compile(indoc! {"
+        const int MAX = 3;
+        class C_NPC { var int id; };
+        var int counter;
+        var float speeds[MAX];
+        var string names[2];
+        var func callback;
+        var C_NPC hero;
+        "});
+
+        // $INSTANCE_HELP, MAX, C_NPC, C_NPC.ID, COUNTER, SPEEDS, NAMES, CALLBACK, HERO
+        let symbols = &dat.symbols[4..];
+
+        let summary: Vec<_> = symbols
+            .iter()
+            .map(|s| {
+                let props = &s.props.elem_props;
+                (props.data_type(), props.count(), &s.data)
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (DataType::Int, 1, &SymbolData::Int(vec![0])),
+                (DataType::Float, 3, &SymbolData::Float(vec![0.0; 3])),
+                (
+                    DataType::String,
+                    2,
+                    &SymbolData::String(vec![ZString::default(); 2])
+                ),
+                (DataType::Func, 1, &SymbolData::Address(0)),
+                (DataType::Instance, 1, &SymbolData::Address(0)),
+            ]
+        );
+        assert!(symbols
+            .iter()
+            .all(|s| s.props.elem_props.flags().is_empty() && s.parent.is_none()));
+    }
+
+    #[test]
+    fn class_layouts() {
+        let src = indoc! {"
+        class C_NPC { var int id; var string name; };
+        class C_INFO {
+            var int npc;
+            var int nr;
+            var int important;
+            var func condition;
+            var func information;
+            var string description;
+            var int trade;
+            var int permanent;
+        };
+        class C_CUSTOM { var int a; var float b; };
+        class C_ITEM { var int id; var string nameID; var string name; var int unknown; };
+        class C_SVM { var string smalltalk01; var int x; };
+        class CCAMSYS { var float bestRange; };
+        "};
+
+        let compiled =
+            crate::compile_sources(&CompileOptions::default(), [("test.d", src)]).unwrap();
+
+        let layout: Vec<_> = compiled
+            .output
+            .symbols
+            .iter()
+            .filter(|s| s.props.elem_props.data_type() == DataType::Class)
+            .map(|s| (s.props.off_cls_ret, &s.data))
+            .collect();
+
+        assert_eq!(
+            layout,
+            [
+                (24, &SymbolData::ClassOffset(288)),
+                (48, &SymbolData::ClassOffset(0)),
+                (8, &SymbolData::ClassOffset(0)),
+                (48, &SymbolData::ClassOffset(288)),
+                (24, &SymbolData::ClassOffset(0)),
+                (4, &SymbolData::ClassOffset(0)),
+            ]
+        );
+
+        // $INSTANCE_HELP, C_NPC, C_NPC.ID, C_NPC.NAME
+        let fields: Vec<_> = compiled.output.symbols[2..4]
+            .iter()
+            .map(|s| s.props.off_cls_ret)
+            .collect();
+        assert_eq!(fields, [288, 292]);
+
+        let warnings: Vec<_> = compiled
+            .diagnostics
+            .warnings
+            .iter()
+            .map(|warning| (warning.to_string(), &src[warning.span().clone()]))
+            .collect();
+
+        assert_eq!(
+            warnings,
+            [
+                (
+                    "class `C_NPC` is 24 bytes long, but the engine expects 800 bytes".into(),
+                    "C_NPC"
+                ),
+                (
+                    "class `C_ITEM` is 48 bytes long, but the engine expects 524 bytes".into(),
+                    "C_ITEM"
+                ),
+                (
+                    "field `C_ITEM.nameID` is at offset 4, but the engine expects it at 24".into(),
+                    "nameID"
+                ),
+                (
+                    "field `C_ITEM.name` is at offset 24, but the engine expects it at 4".into(),
+                    "name"
+                ),
+                (
+                    "field `C_ITEM.unknown` is not known to the engine".into(),
+                    "unknown"
+                ),
+                (
+                    "field `C_SVM.x` is of type `int`, but the engine expects `string`".into(),
+                    "int"
+                ),
+                (
+                    "layout of engine class `CCAMSYS` is not known for Gothic 2, its fields are not checked".into(),
+                    "CCAMSYS"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn class_layouts_per_game() {
+        let src = indoc! {"
+        class C_NPC { var int id; var string name[5]; var string slot; var int npcType; };
+        class C_FOCUS { var float npc_longrange; };
+        class C_GILVALUES { var int water_depth_knee[1]; };
+        "};
+
+        let options = CompileOptions {
+            game: Game::Gothic1,
+            ..Default::default()
+        };
+        let compiled = crate::compile_sources(&options, [("test.d", src)]).unwrap();
+
+        let warnings: Vec<_> = compiled
+            .diagnostics
+            .warnings
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect();
+
+        // Gothic 1 has no `effect` field in front of `npcType`, and no `C_GILVALUES` at all
+        assert_eq!(
+            warnings,
+            [
+                "class `C_NPC` is 128 bytes long, but the engine expects 552 bytes",
+                "class `C_FOCUS` is 4 bytes long, but the engine expects 76 bytes",
+            ]
+        );
+
+        let compiled =
+            crate::compile_sources(&CompileOptions::default(), [("test.d", src)]).unwrap();
+        assert_eq!(
+            compiled.diagnostics.warnings[1].to_string(),
+            "field `C_NPC.npcType` is at offset 124, but the engine expects it at 144"
+        );
+        assert_eq!(
+            compiled.diagnostics.warnings[3].to_string(),
+            "layout of engine class `C_GILVALUES` is not known for Gothic 2, its fields are not checked"
+        );
+    }
+
+    #[test]
+    fn instance_class_fields() {
+        let src = "class C_TEST { var C_TEST other; };";
+        let diagnostics =
+            crate::compile_sources(&CompileOptions::default(), [("test.d", src)]).unwrap_err();
+
+        assert_eq!(
+            diagnostics.errors[0].to_string(),
+            "class field of instance type is not supported"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let a = indoc! {"
+        func void a() {
+            unknown_fn();
+            var int x;
+            x = 1;
+            x[IDX] = 2;
+        };
+        "};
+        let b = indoc! {"
+        extern func void NOT_A_BUILTIN()
+        var Foo bar;
+        const int C = missing;
+        "};
+
+        let diagnostics =
+            crate::compile_sources(&CompileOptions::default(), [("a.d", a), ("b.d", b)])
+                .unwrap_err();
+        let errors: Vec<_> = diagnostics
+            .errors
+            .iter()
+            .map(|err| {
+                let src = diagnostics.files.source(err.file());
+                (err.to_string(), &src[err.span().clone()])
+            })
+            .collect();
+
+        assert_eq!(
+            errors,
+            [
+                ("unknown identifier `missing`".into(), "missing"),
+                ("unknown identifier `unknown_fn`".into(), "unknown_fn"),
+                (
+                    "array index has to be a constant intager in 0..=255 range".into(),
+                    "IDX"
+                ),
+                (
+                    "address of extern function `NOT_A_BUILTIN` is not known for Gothic 2".into(),
+                    "NOT_A_BUILTIN"
+                ),
+                ("unknown type `Foo`".into(), "Foo"),
+            ]
+        );
+    }
+
+    #[test]
+    fn extern_per_game() {
+        let src = "extern func string IntToString(var int x)";
+
+        let options = CompileOptions {
+            game: Game::Gothic2,
+            ..Default::default()
+        };
+        let dat = crate::compile_sources(&options, [("test.d", src)])
+            .unwrap()
+            .output;
+        assert_eq!(dat.symbols[1].data, SymbolData::Address(0x548a80));
+
+        // No built-in table for Gothic 1
+        let gothic1 = CompileOptions {
+            game: Game::Gothic1,
+            ..Default::default()
+        };
+        let errors = crate::compile_sources(&gothic1, [("test.d", src)])
+            .unwrap_err()
+            .errors;
+        assert!(matches!(
+            &errors[..],
+            [CompileError::UnknownExtern {
+                game: Game::Gothic1,
+                ..
+            }]
+        ));
+
+        // Stands in for the `GOTHIC.DAT` shipped with Gothic 1
+        let original = crate::compile_sources(
+            &CompileOptions {
+                externs: ExternMap::parse("IntToString = 0x123456").unwrap(),
+                ..options
+            },
+            [("test.d", src)],
+        )
+        .unwrap()
+        .output;
+
+        let options = CompileOptions {
+            game: Game::Gothic1,
+            externs: ExternMap::from_dat(&original),
+            ..Default::default()
+        };
+        let dat = crate::compile_sources(&options, [("test.d", src)])
+            .unwrap()
+            .output;
+        assert_eq!(dat.symbols[1].data, SymbolData::Address(0x123456));
+    }
+}
@@ -1,10 +1,15 @@
 #![allow(dead_code)]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use daedalus_parser::{Expr, ExprKind, LitKind, UnaryOp};
+use dat_file::properties::DataType;
 
-use crate::{files::File, symbol_indices::SymbolIndices};
+use crate::{
+    error::CompileError,
+    files::{File, FileId},
+    symbol_indices::{self, SymbolIndices},
+};
 
 #[derive(Debug)]
 pub enum Value {
@@ -18,7 +23,7 @@ pub enum Value {
 /// Const nodes in the tree
 #[derive(Default)]
 struct ConstNodes<'a> {
-    map: HashMap<&'a str, &'a daedalus_parser::Const>,
+    map: HashMap<String, (FileId, &'a daedalus_parser::Const)>,
 }
 
 impl<'a> ConstNodes<'a> {
@@ -30,13 +35,13 @@ impl<'a> ConstNodes<'a> {
 
     fn visit_file(&mut self, file: &'a File) {
         for item in file.ast.items.iter() {
-            self.visit_item(item);
+            self.visit_item(file.id, item);
         }
     }
 
-    fn visit_item(&mut self, item: &'a daedalus_parser::Item) {
+    fn visit_item(&mut self, file: FileId, item: &'a daedalus_parser::Item) {
         if let daedalus_parser::Item::Const(item) = item {
-            self.map.insert(&item.ident.raw, item);
+            self.map.insert(item.ident.raw.to_uppercase(), (file, item));
         }
     }
 }
@@ -46,21 +51,31 @@ pub struct ConstValues {
 }
 
 impl ConstValues {
+    /// Constants that failed to evaluate are left out of the map, errors get pushed to `errors`
     pub fn build<'a>(
         files: impl IntoIterator<Item = &'a File> + Clone,
         indices: &'a SymbolIndices,
+        errors: &mut Vec<CompileError>,
     ) -> Self {
         let mut map = ConstNodes::default();
         map.visit_files(files.clone());
 
-        let mut eval = ConstEvaluator { indices, map };
+        let mut eval = ConstEvaluator {
+            indices,
+            map,
+            in_progress: HashSet::new(),
+        };
 
         let mut map = HashMap::new();
         for file in files {
             for item in file.ast.items.iter() {
                 if let daedalus_parser::Item::Const(item) = item {
-                    let value = eval.visit_const(item);
-                    map.insert(item.ident.raw.to_uppercase(), value);
+                    match eval.visit_const(file.id, item) {
+                        Ok(value) => {
+                            map.insert(item.ident.raw.to_uppercase(), value);
+                        }
+                        Err(err) => errors.push(err),
+                    }
                 }
             }
         }
@@ -72,34 +87,74 @@ impl ConstValues {
 struct ConstEvaluator<'a> {
     map: ConstNodes<'a>,
     indices: &'a SymbolIndices,
+    /// Constants whose value is being evaluated, referring to one of them is a cycle
+    in_progress: HashSet<String>,
 }
 
 impl<'a> ConstEvaluator<'a> {
-    fn visit_const(&mut self, item: &daedalus_parser::Const) -> Value {
-        match &item.kind {
-            daedalus_parser::ConstKind::Value { init } => self.visit_expr(init),
+    fn visit_const(
+        &mut self,
+        file: FileId,
+        item: &daedalus_parser::Const,
+    ) -> Result<Value, CompileError> {
+        let name = item.ident.raw.to_uppercase();
+
+        self.in_progress.insert(name.clone());
+        let value = self.eval_const(file, item);
+        self.in_progress.remove(&name);
+
+        value
+    }
+
+    fn eval_const(
+        &mut self,
+        file: FileId,
+        item: &daedalus_parser::Const,
+    ) -> Result<Value, CompileError> {
+        let value = match &item.kind {
+            daedalus_parser::ConstKind::Value { init } => self.visit_expr(file, init)?,
             daedalus_parser::ConstKind::Array { size_init: _, init } => {
-                let values: Vec<_> = init.iter().map(|expr| self.visit_expr(expr)).collect();
-                // todo!("{}[{}] = {:?}", item.ident.raw, values.len(), values)
+                let values = init
+                    .iter()
+                    .map(|expr| self.visit_expr(file, expr))
+                    .collect::<Result<_, _>>()?;
                 Value::Array(values)
             }
+        };
+
+        // `const float F = 1;` holds a float, the type checker only lets int literals through
+        if symbol_indices::data_type(&item.ty.raw) == DataType::Float {
+            return Ok(into_float(value));
         }
+
+        Ok(value)
     }
 
-    fn visit_expr(&mut self, expr: &Expr) -> Value {
-        match &expr.kind {
+    fn visit_expr(&mut self, file: FileId, expr: &Expr) -> Result<Value, CompileError> {
+        let unsupported = |what| CompileError::Unsupported {
+            what,
+            file,
+            span: expr.span.clone(),
+        };
+        let invalid = |reason| CompileError::ConstEval {
+            reason,
+            file,
+            span: expr.span.clone(),
+        };
+
+        let value = match &expr.kind {
             ExprKind::Binary(op, left, right) => {
-                let left = self.visit_expr(left);
-                let right = self.visit_expr(right);
+                let left = self.visit_expr(file, left)?;
+                let right = self.visit_expr(file, right)?;
 
                 let (left, right) = match (left, right) {
                     (Value::Int(l), Value::Int(r)) => (l, r),
-                    _ => todo!(),
+                    _ => return Err(unsupported("arithmetic on non-intager constants")),
                 };
 
                 match op {
-                    daedalus_parser::AssocOp::Add => Value::Int(left + right),
-                    daedalus_parser::AssocOp::Subtract => Value::Int(left - right),
+                    daedalus_parser::AssocOp::Add => Value::Int(left.wrapping_add(right)),
+                    daedalus_parser::AssocOp::Subtract => Value::Int(left.wrapping_sub(right)),
                     daedalus_parser::AssocOp::Equal => Value::Int((left == right) as i32),
                     daedalus_parser::AssocOp::NotEqual => Value::Int((left != right) as i32),
                     daedalus_parser::AssocOp::Less => Value::Int((left < right) as i32),
@@ -110,27 +165,42 @@ impl<'a> ConstEvaluator<'a> {
                     daedalus_parser::AssocOp::BitAnd => Value::Int(left & right),
                     daedalus_parser::AssocOp::Or => Value::Int((left > 0 || right > 0) as i32),
                     daedalus_parser::AssocOp::BitOr => Value::Int(left | right),
-                    daedalus_parser::AssocOp::Multiply => Value::Int(left * right),
-                    daedalus_parser::AssocOp::Divide => Value::Int(left / right),
-                    daedalus_parser::AssocOp::ShiftLeft => Value::Int(left << right),
-                    daedalus_parser::AssocOp::ShiftRight => Value::Int(left >> right),
-                    daedalus_parser::AssocOp::Assign => todo!(),
-                    daedalus_parser::AssocOp::AddAssign => todo!(),
-                    daedalus_parser::AssocOp::SubtractAssign => todo!(),
-                    daedalus_parser::AssocOp::MultiplyAssign => todo!(),
-                    daedalus_parser::AssocOp::DivideAssign => todo!(),
+                    daedalus_parser::AssocOp::Multiply => Value::Int(left.wrapping_mul(right)),
+                    daedalus_parser::AssocOp::Divide => match right {
+                        0 => return Err(invalid("division by zero")),
+                        right => Value::Int(left.wrapping_div(right)),
+                    },
+                    daedalus_parser::AssocOp::ShiftLeft => u32::try_from(right)
+                        .ok()
+                        .and_then(|right| left.checked_shl(right))
+                        .map(Value::Int)
+                        .ok_or_else(|| invalid("shift amount has to be in 0..32"))?,
+                    daedalus_parser::AssocOp::ShiftRight => u32::try_from(right)
+                        .ok()
+                        .and_then(|right| left.checked_shr(right))
+                        .map(Value::Int)
+                        .ok_or_else(|| invalid("shift amount has to be in 0..32"))?,
+                    daedalus_parser::AssocOp::Assign
+                    | daedalus_parser::AssocOp::AddAssign
+                    | daedalus_parser::AssocOp::SubtractAssign
+                    | daedalus_parser::AssocOp::MultiplyAssign
+                    | daedalus_parser::AssocOp::DivideAssign => {
+                        return Err(unsupported("assignment in a constant"))
+                    }
                 }
             }
-            ExprKind::Unary(op, expr) => {
-                let value = self.visit_expr(expr);
-                let Value::Int(value) = value else { todo!() };
+            ExprKind::Unary(op, inner) => {
+                let value = self.visit_expr(file, inner)?;
+                let Value::Int(value) = value else {
+                    return Err(unsupported("unary operator on non-intager constant"));
+                };
 
                 Value::Int(match op {
                     UnaryOp::Not => match value {
                         0 => 1,
                         _ => 0,
                     },
-                    UnaryOp::Negative => -value,
+                    UnaryOp::Negative => value.wrapping_neg(),
                 })
             }
             ExprKind::Lit(lit) => match &lit.kind {
@@ -138,20 +208,41 @@ impl<'a> ConstEvaluator<'a> {
                 LitKind::Float(v) => Value::Float(*v),
                 LitKind::String(v) => Value::String(v.clone()),
             },
-            ExprKind::Call(_) => todo!(),
+            ExprKind::Call(_) => return Err(unsupported("function call in a constant")),
             ExprKind::Ident(ident) => {
-                if let Some(ref_item) = self.map.map.get(ident.raw.to_uppercase().as_str()) {
-                    self.visit_const(ref_item)
-                } else if let Some(symbol) = self.indices.get(&ident.raw.to_uppercase()) {
+                let name = ident.raw.to_uppercase();
+                if self.in_progress.contains(&name) {
+                    return Err(CompileError::ConstCycle {
+                        ident: ident.raw.clone(),
+                        file,
+                        span: ident.span.clone(),
+                    });
+                } else if let Some(&(file, ref_item)) = self.map.map.get(&name) {
+                    self.visit_const(file, ref_item)?
+                } else if let Some(symbol) = self.indices.get(&name) {
                     Value::Symbol(symbol.id)
                 } else {
-                    todo!()
+                    return Err(CompileError::UnknownIdent {
+                        ident: ident.raw.clone(),
+                        file,
+                        span: ident.span.clone(),
+                    });
                 }
             }
-            ExprKind::Paren(_) => todo!(),
-            ExprKind::Field(_, _) => todo!(),
-            ExprKind::Index(_, _) => todo!(),
-        }
+            ExprKind::Paren(inner) => self.visit_expr(file, inner)?,
+            ExprKind::Field(_, _) => return Err(unsupported("field access in a constant")),
+            ExprKind::Index(_, _) => return Err(unsupported("array index in a constant")),
+        };
+
+        Ok(value)
+    }
+}
+
+fn into_float(value: Value) -> Value {
+    match value {
+        Value::Int(v) => Value::Float(v as f32),
+        Value::Array(values) => Value::Array(values.into_iter().map(into_float).collect()),
+        value => value,
     }
 }
 
@@ -174,6 +265,60 @@ mod tests {
 
         let indices = SymbolIndices::build(&files);
 
-        ConstValues::build(&files, &indices);
+        let mut errors = Vec::new();
+        let values = ConstValues::build(&files, &indices, &mut errors);
+
+        assert!(errors.is_empty());
+        assert!(matches!(values.map["CBA"], Value::Int(6)));
+    }
+
+    #[test]
+    fn invalid() {
+        let src = indoc! {"
+        const int DIV = 1 / 0;
+        const int SHL = 1 << 32;
+        const int SHR = 1 >> -1;
+        const int A = B + 1;
+        const int B = A;
+        const int SELF_REF = SELF_REF;
+        const int WRAPS = 2147483647 + 1;
+        "};
+
+        let mut files_store = Files::new();
+        let files = [files_store.parse("invalid.d", src).unwrap()];
+
+        let indices = SymbolIndices::build(&files);
+
+        let mut errors = Vec::new();
+        let values = ConstValues::build(&files, &indices, &mut errors);
+
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|err| (err.to_string(), &src[err.span().clone()]))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    "constant can not be evaluated: division by zero".to_string(),
+                    "1 / 0"
+                ),
+                (
+                    "constant can not be evaluated: shift amount has to be in 0..32".to_string(),
+                    "1 << 32"
+                ),
+                (
+                    "constant can not be evaluated: shift amount has to be in 0..32".to_string(),
+                    "1 >> -1"
+                ),
+                ("constant `A` depends on itself".to_string(), "A"),
+                ("constant `B` depends on itself".to_string(), "B"),
+                (
+                    "constant `SELF_REF` depends on itself".to_string(),
+                    "SELF_REF"
+                ),
+            ]
+        );
+        assert!(matches!(values.map["WRAPS"], Value::Int(i32::MIN)));
     }
 }
@@ -1,4 +1,4 @@
-use std::io::Write;
+use std::{collections::HashMap, io::Write};
 
 use byteorder::{LittleEndian, WriteBytesExt};
 use dat_file::{
@@ -14,6 +14,9 @@ pub struct DatSymbolTable {
     symbols: Vec<Symbol>,
     generated_symbols_start: usize,
     autogenerated_symbols: Vec<Symbol>,
+    /// Symbols of string literals by their value, `None` if every literal gets its own symbol, the
+    /// way zengin does it
+    string_pool: Option<HashMap<ZString, u32>>,
 }
 
 impl DatSymbolTable {
@@ -22,6 +25,7 @@ impl DatSymbolTable {
             symbols: Vec::with_capacity(symbol_indices.len()),
             generated_symbols_start: symbol_indices.len(),
             autogenerated_symbols: Vec::new(),
+            string_pool: None,
         };
 
         this.push_symbol(Symbol {
@@ -45,8 +49,18 @@ impl DatSymbolTable {
         this
     }
 
+    /// Let identical string literals share a single symbol
+    pub fn with_string_pooling(mut self, enabled: bool) -> Self {
+        self.string_pool = enabled.then(HashMap::new);
+        self
+    }
+
     /// "abc"
     pub fn string(&mut self, value: ZString) -> u32 {
+        if let Some(id) = self.string_pool.as_ref().and_then(|pool| pool.get(&value)) {
+            return *id;
+        }
+
         let symbol_id = 10000 + self.autogenerated_symbols.len();
         let mut symbol_str = format!("{symbol_id}").into_bytes();
         symbol_str.insert(0, 0xFF);
@@ -66,10 +80,14 @@ impl DatSymbolTable {
                 },
             },
             code_span: SymbolCodeSpan::empty(0),
-            data: SymbolData::String(vec![value]),
+            data: SymbolData::String(vec![value.clone()]),
             parent: None,
         });
 
+        if let Some(pool) = self.string_pool.as_mut() {
+            pool.insert(value, id as u32);
+        }
+
         id as u32
     }
 
@@ -212,13 +230,13 @@ impl DatSymbolTable {
         name: ZString,
         span: SymbolCodeSpan,
         fields: &[(ZString, DataType, u32, SymbolCodeSpan)],
+        size: i32,
         offset: i32,
-        address: i32,
     ) -> u32 {
         let class_symbol = self.push_symbol(Symbol {
             name: Some(name.clone()),
             props: Properties {
-                off_cls_ret: offset,
+                off_cls_ret: size,
                 elem_props: {
                     let mut default = ElemProps::default();
                     default.set_count(fields.len() as u32);
@@ -229,11 +247,11 @@ impl DatSymbolTable {
                 },
             },
             code_span: span,
-            data: SymbolData::Address(address),
+            data: SymbolData::Address(offset),
             parent: None,
         });
 
-        let mut address = address;
+        let mut address = offset;
         for (ident, data_type, count, span) in fields.iter() {
             let mut name = name.clone();
             name.0.push_char('.');
@@ -247,19 +265,7 @@ impl DatSymbolTable {
                         let mut default = ElemProps::default();
                         default.set_count(*count);
 
-                        let size = match data_type {
-                            DataType::Void => 0,
-                            DataType::Float => 4,
-                            DataType::Int => 4,
-                            // class zSTRING { int allocater; char* vector; int length; int reserved; }
-                            DataType::String => 20,
-                            DataType::Class => todo!(),
-                            DataType::Func => 4,
-                            DataType::Prototype => todo!(),
-                            DataType::Instance => todo!(),
-                        };
-
-                        address += *count as i32 * size;
+                        address += *count as i32 * class_field_size(*data_type);
                         default.set_data_type(*data_type);
                         default.set_flags(PropFlag::CLASS_VAR);
                         default.set_space(1);
@@ -288,7 +294,10 @@ impl DatSymbolTable {
                 elem_props: {
                     let mut default = ElemProps::default();
                     default.set_count(1);
-                    default.set_data_type(DataType::Int);
+                    default.set_data_type(match data {
+                        const_eval::Value::Float(_) => DataType::Float,
+                        _ => DataType::Int,
+                    });
                     default.set_flags(PropFlag::CONST);
                     default.set_space(1);
                     default
@@ -306,6 +315,42 @@ impl DatSymbolTable {
         })
     }
 
+    /// var type name([count])?
+    pub fn var(
+        &mut self,
+        name: ZString,
+        code_span: SymbolCodeSpan,
+        data_type: DataType,
+        count: u32,
+    ) -> u32 {
+        self.push_symbol(Symbol {
+            name: Some(name),
+            props: Properties {
+                off_cls_ret: 0,
+                elem_props: {
+                    let mut default = ElemProps::default();
+                    default.set_count(count);
+                    default.set_data_type(data_type);
+                    default.set_flags(PropFlag::empty());
+                    default.set_space(1);
+                    default
+                },
+            },
+            code_span,
+            data: match data_type {
+                DataType::Float => SymbolData::Float(vec![0.0; count as usize]),
+                DataType::Int => SymbolData::Int(vec![0; count as usize]),
+                DataType::String => SymbolData::String(vec![ZString::default(); count as usize]),
+                DataType::Class => SymbolData::ClassOffset(0),
+                DataType::Func => SymbolData::Address(0),
+                DataType::Prototype => SymbolData::Address(0),
+                DataType::Instance => SymbolData::Address(0),
+                DataType::Void => SymbolData::None,
+            },
+            parent: None,
+        })
+    }
+
     pub fn instance(
         &mut self,
         name: ZString,
@@ -332,6 +377,53 @@ impl DatSymbolTable {
         })
     }
 
+    pub fn prototype(
+        &mut self,
+        name: ZString,
+        code_span: SymbolCodeSpan,
+        address: u32,
+        parent: u32,
+    ) -> u32 {
+        self.push_symbol(Symbol {
+            name: Some(name),
+            props: Properties {
+                off_cls_ret: 0,
+                elem_props: {
+                    let mut default = ElemProps::default();
+                    default.set_count(0);
+                    default.set_data_type(DataType::Prototype);
+                    default.set_flags(PropFlag::CONST);
+                    default.set_space(1);
+                    default
+                },
+            },
+            code_span,
+            data: SymbolData::Address(address as i32),
+            parent: Some(parent),
+        })
+    }
+
+    /// Bytecode address of a script function or prototype
+    pub fn func_address(&self, id: u32) -> Option<u32> {
+        let symbol = self.symbols.get(id as usize)?;
+        let props = &symbol.props.elem_props;
+
+        let callable = match props.data_type() {
+            DataType::Func => !props.flags().contains(PropFlag::EXTERNAL),
+            DataType::Prototype => true,
+            _ => false,
+        };
+
+        if !callable {
+            return None;
+        }
+
+        match symbol.data {
+            SymbolData::Address(address) => Some(address as u32),
+            _ => None,
+        }
+    }
+
     fn generate_sort_table(&self) -> Vec<u32> {
         let mut symbol_ids: Vec<_> = self
             .symbols
@@ -374,3 +466,29 @@ impl DatSymbolTable {
         }
     }
 }
+
+/// Size of all class fields combined, in bytes
+pub fn class_size(fields: &[(ZString, DataType, u32, SymbolCodeSpan)]) -> i32 {
+    fields
+        .iter()
+        .map(|(_, data_type, count, _)| *count as i32 * class_field_size(*data_type))
+        .sum()
+}
+
+/// Size of a single class field element, in bytes, `None` for types a class can't hold
+pub fn field_size(data_type: DataType) -> Option<i32> {
+    match data_type {
+        DataType::Void => Some(0),
+        DataType::Float => Some(4),
+        DataType::Int => Some(4),
+        // class zSTRING { int allocater; char* vector; int length; int reserved; }
+        DataType::String => Some(20),
+        DataType::Func => Some(4),
+        DataType::Class | DataType::Prototype | DataType::Instance => None,
+    }
+}
+
+/// Types of class fields get checked by the compiler before the class is emitted
+fn class_field_size(data_type: DataType) -> i32 {
+    field_size(data_type).expect("class field of a type without a size")
+}
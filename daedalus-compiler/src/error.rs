@@ -0,0 +1,243 @@
+use codespan_reporting::{
+    diagnostic::{Diagnostic, Label},
+    term::{
+        self,
+        termcolor::{ColorChoice, StandardStream},
+    },
+};
+use daedalus_bytecode::RelocationError;
+use daedalus_parser::ParseError;
+use dat_file::properties::DataType;
+use logos::Span;
+
+use crate::{
+    files::{FileId, Files},
+    game::Game,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompileError {
+    #[error("{err}")]
+    Parse { err: ParseError, file: FileId },
+    #[error("unknown identifier `{ident}`")]
+    UnknownIdent {
+        ident: String,
+        file: FileId,
+        span: Span,
+    },
+    #[error("unknown type `{ty}`")]
+    UnknownType {
+        ty: String,
+        file: FileId,
+        span: Span,
+    },
+    #[error("address of extern function `{ident}` is not known for {game}")]
+    UnknownExtern {
+        ident: String,
+        game: Game,
+        file: FileId,
+        span: Span,
+    },
+    #[error("`{ident}` is not a function")]
+    NotCallable {
+        ident: String,
+        file: FileId,
+        span: Span,
+    },
+    #[error("array index has to be a constant intager in 0..=255 range")]
+    InvalidArrayIndex { file: FileId, span: Span },
+    #[error("array size has to be a positive constant intager")]
+    InvalidArraySize { file: FileId, span: Span },
+    #[error("mismatched types: expected `{expected}`, found `{found}`")]
+    TypeMismatch {
+        expected: DataType,
+        found: DataType,
+        file: FileId,
+        span: Span,
+    },
+    #[error("function `{ident}` takes {expected} argument(s), but {found} were supplied")]
+    ArgumentCount {
+        ident: String,
+        expected: usize,
+        found: usize,
+        file: FileId,
+        span: Span,
+    },
+    #[error("a void function can not return a value")]
+    UnexpectedReturnValue { file: FileId, span: Span },
+    #[error("missing return value of type `{expected}`")]
+    MissingReturnValue {
+        expected: DataType,
+        file: FileId,
+        span: Span,
+    },
+    #[error("constant `{ident}` depends on itself")]
+    ConstCycle {
+        ident: String,
+        file: FileId,
+        span: Span,
+    },
+    #[error("constant can not be evaluated: {reason}")]
+    ConstEval {
+        reason: &'static str,
+        file: FileId,
+        span: Span,
+    },
+    #[error("{what} is not supported")]
+    Unsupported {
+        what: &'static str,
+        file: FileId,
+        span: Span,
+    },
+    #[error("{err}")]
+    Relocation {
+        err: RelocationError,
+        file: FileId,
+        span: Span,
+    },
+}
+
+impl CompileError {
+    pub fn file(&self) -> FileId {
+        match self {
+            CompileError::Parse { file, .. }
+            | CompileError::UnknownIdent { file, .. }
+            | CompileError::UnknownType { file, .. }
+            | CompileError::UnknownExtern { file, .. }
+            | CompileError::NotCallable { file, .. }
+            | CompileError::InvalidArrayIndex { file, .. }
+            | CompileError::InvalidArraySize { file, .. }
+            | CompileError::TypeMismatch { file, .. }
+            | CompileError::ArgumentCount { file, .. }
+            | CompileError::UnexpectedReturnValue { file, .. }
+            | CompileError::MissingReturnValue { file, .. }
+            | CompileError::ConstCycle { file, .. }
+            | CompileError::ConstEval { file, .. }
+            | CompileError::Unsupported { file, .. }
+            | CompileError::Relocation { file, .. } => *file,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            CompileError::Parse { err, .. } => err.span(),
+            CompileError::UnknownIdent { span, .. }
+            | CompileError::UnknownType { span, .. }
+            | CompileError::UnknownExtern { span, .. }
+            | CompileError::NotCallable { span, .. }
+            | CompileError::InvalidArrayIndex { span, .. }
+            | CompileError::InvalidArraySize { span, .. }
+            | CompileError::TypeMismatch { span, .. }
+            | CompileError::ArgumentCount { span, .. }
+            | CompileError::UnexpectedReturnValue { span, .. }
+            | CompileError::MissingReturnValue { span, .. }
+            | CompileError::ConstCycle { span, .. }
+            | CompileError::ConstEval { span, .. }
+            | CompileError::Unsupported { span, .. }
+            | CompileError::Relocation { span, .. } => span,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompileWarning {
+    #[error("class `{class}` is {found} bytes long, but the engine expects {expected} bytes")]
+    ClassLayoutMismatch {
+        class: String,
+        expected: i32,
+        found: i32,
+        file: FileId,
+        span: Span,
+    },
+    #[error("field `{field}` is at offset {found}, but the engine expects it at {expected}")]
+    FieldOffsetMismatch {
+        field: String,
+        expected: i32,
+        found: i32,
+        file: FileId,
+        span: Span,
+    },
+    #[error("field `{field}` is of type `{found}`, but the engine expects `{expected}`")]
+    FieldTypeMismatch {
+        field: String,
+        expected: DataType,
+        found: DataType,
+        file: FileId,
+        span: Span,
+    },
+    #[error("field `{field}` is not known to the engine")]
+    UnknownClassField {
+        field: String,
+        file: FileId,
+        span: Span,
+    },
+    #[error(
+        "layout of engine class `{class}` is not known for {game}, its fields are not checked"
+    )]
+    UnknownClassLayout {
+        class: String,
+        game: Game,
+        file: FileId,
+        span: Span,
+    },
+}
+
+impl CompileWarning {
+    pub fn file(&self) -> FileId {
+        match self {
+            CompileWarning::ClassLayoutMismatch { file, .. }
+            | CompileWarning::FieldOffsetMismatch { file, .. }
+            | CompileWarning::FieldTypeMismatch { file, .. }
+            | CompileWarning::UnknownClassField { file, .. }
+            | CompileWarning::UnknownClassLayout { file, .. } => *file,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            CompileWarning::ClassLayoutMismatch { span, .. }
+            | CompileWarning::FieldOffsetMismatch { span, .. }
+            | CompileWarning::FieldTypeMismatch { span, .. }
+            | CompileWarning::UnknownClassField { span, .. }
+            | CompileWarning::UnknownClassLayout { span, .. } => span,
+        }
+    }
+}
+
+pub fn emit_errors(files: &Files, errors: &[CompileError]) {
+    for err in errors {
+        emit(files, Diagnostic::error(), err, err.file(), err.span());
+    }
+}
+
+pub fn emit_warnings(files: &Files, warnings: &[CompileWarning]) {
+    for warning in warnings {
+        emit(
+            files,
+            Diagnostic::warning(),
+            warning,
+            warning.file(),
+            warning.span(),
+        );
+    }
+}
+
+fn emit(
+    files: &Files,
+    diagnostic: Diagnostic<FileId>,
+    message: &impl ToString,
+    file: FileId,
+    span: &Span,
+) {
+    let writer = StandardStream::stderr(ColorChoice::Always);
+    let config = term::Config::default();
+
+    let message = message.to_string();
+    let diagnostic = diagnostic
+        .with_message(message.clone())
+        .with_labels(vec![
+            Label::primary(file, span.clone()).with_message(message)
+        ]);
+
+    term::emit(&mut writer.lock(), &config, files, &diagnostic).unwrap();
+}
@@ -6,6 +6,8 @@ use codespan::{ByteIndex, LineIndex, Location};
 use codespan_reporting::files::Error;
 use daedalus_parser::{DaedalusLexer, DaedalusParser};
 
+use crate::{cache::ParseCache, error::CompileError};
+
 pub struct File {
     pub id: FileId,
     pub ast: daedalus_parser::File,
@@ -18,20 +20,27 @@ pub struct FileId {
 }
 
 impl FileId {
+    /// Id of the file as stored in symbol code spans
     pub fn raw(&self) -> u32 {
-        if cfg!(feature = "code-span-compat") {
-            // In zengin first file and builtins share 0 as their id
-            self.id.saturating_sub(1)
-        } else {
-            self.id
-        }
+        self.id
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Files<'a> {
     inner: codespan::Files<&'a str>,
     len: usize,
+    code_span_compat: bool,
+}
+
+impl<'a> Default for Files<'a> {
+    fn default() -> Self {
+        Self {
+            inner: codespan::Files::default(),
+            len: 0,
+            code_span_compat: cfg!(feature = "code-span-compat"),
+        }
+    }
 }
 
 impl<'a> Files<'a> {
@@ -39,24 +48,104 @@ impl<'a> Files<'a> {
         Self::default()
     }
 
+    /// Emit symbol code spans the way zengin does, including its quirks
+    pub fn with_code_span_compat(mut self, code_span_compat: bool) -> Self {
+        self.code_span_compat = code_span_compat;
+        self
+    }
+
+    pub fn code_span_compat(&self) -> bool {
+        self.code_span_compat
+    }
+
     pub fn parse(
         &mut self,
         name: impl Into<OsString>,
         source: &'a str,
-    ) -> daedalus_parser::Result<File> {
-        self.len += 1;
-        let id = FileId {
-            inner: self.inner.add(name, source),
-            id: self.len as u32 - 1,
-        };
+    ) -> Result<File, CompileError> {
+        let id = self.add(name, source);
 
         let ast = daedalus_parser::File::parse(&mut DaedalusParser {
             lexer: &mut DaedalusLexer::new(source),
-        })?;
+        })
+        .map_err(|err| CompileError::Parse { err, file: id })?;
 
         Ok(File { id, ast })
     }
 
+    /// Same as [`Self::parse`], but files are parsed in parallel, results are in the same order as
+    /// `sources`
+    pub fn parse_many<N: Into<OsString>>(
+        &mut self,
+        sources: impl IntoIterator<Item = (N, &'a str)>,
+    ) -> Vec<Result<File, CompileError>> {
+        let (ids, sources): (Vec<_>, Vec<_>) = sources
+            .into_iter()
+            .map(|(name, source)| (self.add(name, source), source))
+            .unzip();
+
+        daedalus_parser::File::parse_many(&sources)
+            .into_iter()
+            .zip(ids)
+            .map(|(ast, id)| match ast {
+                Ok(ast) => Ok(File { id, ast }),
+                Err(err) => Err(CompileError::Parse { err, file: id }),
+            })
+            .collect()
+    }
+
+    /// Same as [`Self::parse_many`], but ASTs of files that did not change since they were last
+    /// parsed are taken from `cache`, and freshly parsed ones get stored in it
+    pub fn parse_many_cached<N: Into<OsString>>(
+        &mut self,
+        sources: impl IntoIterator<Item = (N, &'a str)>,
+        cache: &ParseCache,
+    ) -> Vec<Result<File, CompileError>> {
+        let files: Vec<_> = sources
+            .into_iter()
+            .map(|(name, source)| (self.add(name, source), source, cache.load(source)))
+            .collect();
+
+        let missing: Vec<&str> = files
+            .iter()
+            .filter(|(_, _, ast)| ast.is_none())
+            .map(|(_, source, _)| *source)
+            .collect();
+        let mut parsed = daedalus_parser::File::parse_many(&missing).into_iter();
+
+        files
+            .into_iter()
+            .map(|(id, source, cached)| {
+                let ast = match cached {
+                    Some(ast) => ast,
+                    None => {
+                        let ast = parsed
+                            .next()
+                            .unwrap()
+                            .map_err(|err| CompileError::Parse { err, file: id })?;
+                        cache.store(source, &ast);
+                        ast
+                    }
+                };
+                Ok(File { id, ast })
+            })
+            .collect()
+    }
+
+    fn add(&mut self, name: impl Into<OsString>, source: &'a str) -> FileId {
+        self.len += 1;
+        let id = self.len as u32 - 1;
+        FileId {
+            inner: self.inner.add(name, source),
+            id: if self.code_span_compat {
+                // In zengin first file and builtins share 0 as their id
+                id.saturating_sub(1)
+            } else {
+                id
+            },
+        }
+    }
+
     /// Get the name of the source file.
     ///
     /// ```rust
@@ -194,3 +283,29 @@ impl<'a> Files<'a> {
         self.inner.source_slice(file_id.inner, span)
     }
 }
+
+impl<'f, 'a: 'f> codespan_reporting::files::Files<'f> for Files<'a> {
+    type FileId = FileId;
+    type Name = String;
+    type Source = &'f str;
+
+    fn name(&'f self, id: FileId) -> Result<String, Error> {
+        codespan_reporting::files::Files::name(&self.inner, id.inner)
+    }
+
+    fn source(&'f self, id: FileId) -> Result<&'f str, Error> {
+        codespan_reporting::files::Files::source(&self.inner, id.inner)
+    }
+
+    fn line_index(&'f self, id: FileId, byte_index: usize) -> Result<usize, Error> {
+        codespan_reporting::files::Files::line_index(&self.inner, id.inner, byte_index)
+    }
+
+    fn line_range(
+        &'f self,
+        id: FileId,
+        line_index: usize,
+    ) -> Result<std::ops::Range<usize>, Error> {
+        codespan_reporting::files::Files::line_range(&self.inner, id.inner, line_index)
+    }
+}
@@ -0,0 +1,19 @@
+/// Engine the scripts are compiled for, selects the class layouts and the built-in extern table
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Game {
+    /// Gothic 1, class layouts only. There is no built-in extern table, every extern address has
+    /// to be provided, e.g. taken from the original `GOTHIC.DAT`
+    Gothic1,
+    /// Gothic 2: Night of the Raven
+    #[default]
+    Gothic2,
+}
+
+impl std::fmt::Display for Game {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Game::Gothic1 => f.write_str("Gothic 1"),
+            Game::Gothic2 => f.write_str("Gothic 2"),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::{io::Cursor, path::PathBuf};
+
+use dat_file::DatFile;
+
+mod builtin;
+pub use builtin::{ExternMap, ExternMapError};
+
+mod game;
+pub use game::Game;
+
+mod block_builder;
+
+mod class_layout;
+
+mod compiler;
+use compiler::Compiler;
+
+mod dat_symbol_table;
+
+mod symbol_indices;
+use symbol_indices::SymbolIndices;
+
+mod cache;
+pub use cache::ParseCache;
+
+mod files;
+pub use files::{FileId, Files};
+
+mod error;
+pub use error::{emit_errors, emit_warnings, CompileError, CompileWarning};
+
+mod const_eval;
+use const_eval::ConstValues;
+
+mod type_check;
+use type_check::TypeChecker;
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    pub game: Game,
+    /// Extern addresses of a custom engine build, merged over the built-in table of `game`
+    pub externs: ExternMap,
+    /// Emit symbol code spans the way zengin does, including its quirks
+    pub code_span_compat: bool,
+    /// Let identical string literals share a single symbol, zengin gives each use its own one
+    pub pool_strings: bool,
+    /// Directory to cache parsed files in, unchanged files are not parsed again on the next run
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            game: Game::default(),
+            externs: ExternMap::default(),
+            code_span_compat: cfg!(feature = "code-span-compat"),
+            pool_strings: false,
+            cache_dir: None,
+        }
+    }
+}
+
+/// Errors and warnings of a compilation, along with the sources they point into
+#[derive(Debug)]
+pub struct Diagnostics<'a> {
+    pub files: Files<'a>,
+    pub errors: Vec<CompileError>,
+    pub warnings: Vec<CompileWarning>,
+}
+
+impl Diagnostics<'_> {
+    /// Render all warnings and errors to stderr
+    pub fn emit(&self) {
+        emit_warnings(&self.files, &self.warnings);
+        emit_errors(&self.files, &self.errors);
+    }
+}
+
+/// Output of a successful compilation, along with warnings reported on the way
+#[derive(Debug)]
+pub struct Compiled<'a, T> {
+    pub output: T,
+    pub diagnostics: Diagnostics<'a>,
+}
+
+/// Compile in-memory sources, given as `(name, text)` pairs in the order they would be listed in
+/// a `.src` file
+///
+/// ```
+/// use daedalus_compiler::{compile_sources, CompileOptions};
+///
+/// let dat = compile_sources(
+///     &CompileOptions::default(),
+///     [("test.d", "func int answer() { return 42; };")],
+/// )
+/// .unwrap()
+/// .output;
+///
+/// assert_eq!(dat.symbols.len(), 2);
+/// ```
+pub fn compile_sources<'a>(
+    options: &CompileOptions,
+    sources: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<Compiled<'a, DatFile>, Diagnostics<'a>> {
+    let Compiled {
+        output,
+        diagnostics,
+    } = compile_sources_encoded(options, sources)?;
+
+    Ok(Compiled {
+        output: DatFile::decode(&mut Cursor::new(output))
+            .expect("compiler produced an invalid DAT file"),
+        diagnostics,
+    })
+}
+
+/// Same as [`compile_sources`], but returns the DAT file in its encoded form, ready to be written
+/// to disk
+pub fn compile_sources_encoded<'a>(
+    options: &CompileOptions,
+    sources: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<Compiled<'a, Vec<u8>>, Diagnostics<'a>> {
+    let mut files = Files::new().with_code_span_compat(options.code_span_compat);
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    let parsed = match &options.cache_dir {
+        Some(dir) => files.parse_many_cached(sources, &ParseCache::new(dir)),
+        None => files.parse_many(sources),
+    };
+    let parsed: Vec<_> = parsed
+        .into_iter()
+        .filter_map(|file| file.map_err(|err| errors.push(err)).ok())
+        .collect();
+
+    if errors.is_empty() {
+        match compile(options, &parsed, &files, &mut warnings) {
+            Ok(output) => {
+                return Ok(Compiled {
+                    output,
+                    diagnostics: Diagnostics {
+                        files,
+                        errors,
+                        warnings,
+                    },
+                })
+            }
+            Err(compile_errors) => errors = compile_errors,
+        }
+    }
+
+    Err(Diagnostics {
+        files,
+        errors,
+        warnings,
+    })
+}
+
+/// Compile already parsed files, errors of all of them are collected
+fn compile(
+    options: &CompileOptions,
+    files: &[files::File],
+    span_files: &Files,
+    warnings: &mut Vec<CompileWarning>,
+) -> Result<Vec<u8>, Vec<CompileError>> {
+    let mut errors = Vec::new();
+
+    let symbol_map = SymbolIndices::build(files);
+    TypeChecker::check(files, &symbol_map, &mut errors);
+    let const_values = ConstValues::build(files, &symbol_map, &mut errors);
+
+    match Compiler::new(options, symbol_map, const_values).build(files, span_files, warnings) {
+        Ok(out) if errors.is_empty() => Ok(out),
+        Ok(_) => Err(errors),
+        Err(compile_errors) => {
+            errors.extend(compile_errors);
+            Err(errors)
+        }
+    }
+}
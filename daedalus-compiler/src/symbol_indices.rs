@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
+
+use daedalus_parser::{Block, BlockItem, Var};
+use dat_file::properties::DataType;
 
 use crate::files::File;
 
@@ -14,75 +17,178 @@ pub enum SymbolKind {
 pub struct SymbolIndex {
     pub id: u32,
     pub kind: SymbolKind,
+    /// Type of a variable, or return type of a function
+    pub ty: DataType,
+}
+
+pub struct SymbolIndices {
+    symbols: HashMap<String, SymbolIndex>,
+    /// Class of every prototype, instances derived from a prototype are scoped to it
+    prototype_classes: HashMap<String, String>,
+    /// Class or prototype of every instance and instance typed variable
+    instance_parents: HashMap<String, String>,
+    /// Argument types of every function and extern function
+    arg_types: HashMap<String, Vec<DataType>>,
 }
 
-pub struct SymbolIndices(HashMap<String, SymbolIndex>);
+/// Resolve a type name as written in the source, anything that is not a builtin type is a class
+/// name, so a variable of that type holds an instance
+pub fn data_type(raw: &str) -> DataType {
+    DataType::from_str(raw.to_lowercase().as_str()).unwrap_or(DataType::Instance)
+}
+
+/// All `var` declarations of a function body, including ones nested in `if` blocks, in the order
+/// they get their symbols
+pub fn block_locals(block: &Block) -> Vec<&Var> {
+    fn visit<'a>(block: &'a Block, out: &mut Vec<&'a Var>) {
+        for item in block.items.iter() {
+            match item {
+                BlockItem::Var(var) => out.push(var),
+                BlockItem::If(stmt) => {
+                    let mut stmt = Some(stmt);
+                    while let Some(s) = stmt {
+                        visit(&s.block, out);
+                        stmt = s.next.as_deref();
+                    }
+                }
+                BlockItem::Return(_) | BlockItem::Expr(_) => {}
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    visit(block, &mut out);
+    out
+}
 
 impl std::ops::Deref for SymbolIndices {
     type Target = HashMap<String, SymbolIndex>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.symbols
     }
 }
 
 impl SymbolIndices {
-    fn push_symbol(&mut self, ident: String, kind: SymbolKind) {
-        self.0.insert(
+    /// Push a variable symbol, remembering its class if it holds an instance
+    fn push_var(&mut self, ident: String, var: &Var) {
+        let ty = data_type(&var.ty.raw);
+        if ty == DataType::Instance {
+            self.instance_parents
+                .insert(ident.clone(), var.ty.raw.to_uppercase());
+        }
+        self.push_symbol(ident, SymbolKind::Other, ty);
+    }
+
+    fn push_symbol(&mut self, ident: String, kind: SymbolKind, ty: DataType) {
+        self.symbols.insert(
             ident,
             SymbolIndex {
-                id: self.0.len() as u32,
+                id: self.symbols.len() as u32,
                 kind,
+                ty,
             },
         );
     }
 
+    fn push_arg_types(&mut self, function: String, args: &[Var]) {
+        let types = args.iter().map(|var| data_type(&var.ty.raw)).collect();
+        self.arg_types.insert(function, types);
+    }
+
     fn handle_item(&mut self, item: &daedalus_parser::Item) {
         match item {
             daedalus_parser::Item::ExternFunc(item) => {
                 let ident = item.ident.raw.to_uppercase();
-                self.push_symbol(ident.clone(), SymbolKind::ExternFunction);
+                self.push_symbol(
+                    ident.clone(),
+                    SymbolKind::ExternFunction,
+                    data_type(&item.ty.raw),
+                );
 
                 for var in item.args.iter() {
-                    self.push_symbol(
-                        format!("{}.{}", ident, var.ident.raw.to_uppercase()),
-                        SymbolKind::Other,
-                    );
+                    self.push_var(format!("{}.{}", ident, var.ident.raw.to_uppercase()), var);
                 }
+                self.push_arg_types(ident, &item.args);
             }
             daedalus_parser::Item::Class(item) => {
                 let ident = item.ident.raw.to_uppercase();
-                self.push_symbol(ident.clone(), SymbolKind::Other);
+                self.push_symbol(ident.clone(), SymbolKind::Other, DataType::Class);
 
                 for var in item.fields.iter() {
-                    self.push_symbol(
-                        format!("{}.{}", ident, var.ident.raw.to_uppercase()),
-                        SymbolKind::Other,
-                    );
+                    self.push_var(format!("{}.{}", ident, var.ident.raw.to_uppercase()), var);
                 }
             }
             daedalus_parser::Item::Instance(item) => {
-                self.push_symbol(item.ident.raw.to_uppercase(), SymbolKind::Instance);
+                let ident = item.ident.raw.to_uppercase();
+                self.push_symbol(ident.clone(), SymbolKind::Instance, DataType::Instance);
+
+                self.instance_parents
+                    .insert(ident, item.parent.raw.to_uppercase());
             }
             daedalus_parser::Item::Func(item) => {
-                self.push_symbol(item.ident.raw.to_uppercase(), SymbolKind::Function);
+                let ident = item.ident.raw.to_uppercase();
+                self.push_symbol(ident.clone(), SymbolKind::Function, data_type(&item.ty.raw));
+
+                for var in item.args.iter().chain(block_locals(&item.block)) {
+                    self.push_var(format!("{}.{}", ident, var.ident.raw.to_uppercase()), var);
+                }
+                self.push_arg_types(ident, &item.args);
             }
             daedalus_parser::Item::Const(item) => {
-                self.push_symbol(item.ident.raw.to_uppercase(), SymbolKind::Other);
+                self.push_symbol(
+                    item.ident.raw.to_uppercase(),
+                    SymbolKind::Other,
+                    data_type(&item.ty.raw),
+                );
             }
             daedalus_parser::Item::Var(item) => {
-                self.push_symbol(item.ident.raw.to_uppercase(), SymbolKind::Other);
+                self.push_var(item.ident.raw.to_uppercase(), item);
             }
             daedalus_parser::Item::Prototype(item) => {
-                self.push_symbol(item.ident.raw.to_uppercase(), SymbolKind::Other);
+                let ident = item.ident.raw.to_uppercase();
+                self.push_symbol(ident.clone(), SymbolKind::Other, DataType::Prototype);
+
+                self.prototype_classes
+                    .insert(ident, item.parent.raw.to_uppercase());
             }
         }
     }
 
+    /// Class that members of an instance or prototype derived from `parent` belong to, `parent`
+    /// is either a class or a prototype
+    pub fn parent_class<'a>(&'a self, parent: &'a str) -> &'a str {
+        self.prototype_classes
+            .get(parent)
+            .map(String::as_str)
+            .unwrap_or(parent)
+    }
+
+    /// Class of an instance, or of an instance typed variable, `None` for any other symbol
+    pub fn instance_class(&self, symbol: &str) -> Option<&str> {
+        self.instance_parents
+            .get(symbol)
+            .map(|parent| self.parent_class(parent))
+    }
+
+    /// Argument types of a function or extern function, in the order they are declared
+    pub fn arg_types(&self, function: &str) -> Option<&[DataType]> {
+        self.arg_types.get(function).map(Vec::as_slice)
+    }
+
     pub fn build<'a>(files: impl IntoIterator<Item = &'a File>) -> Self {
-        let mut symbol_map = Self(HashMap::new());
+        let mut symbol_map = Self {
+            symbols: HashMap::new(),
+            prototype_classes: HashMap::new(),
+            instance_parents: HashMap::new(),
+            arg_types: HashMap::new(),
+        };
 
-        symbol_map.push_symbol("$INSTANCE_HELP".to_string(), SymbolKind::Other);
+        symbol_map.push_symbol(
+            "$INSTANCE_HELP".to_string(),
+            SymbolKind::Other,
+            DataType::Instance,
+        );
         for file in files {
             for item in file.ast.items.iter() {
                 symbol_map.handle_item(item);
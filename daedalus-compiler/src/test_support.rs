@@ -0,0 +1,22 @@
+//! Fixtures shared by the tests of the crates working with compiled DAT files
+
+use dat_file::DatFile;
+
+use crate::{compile_sources, CompileOptions, ExternMap};
+
+/// Compiles `src` as a single script named `test.d`, with the built-in externs of the default game plus
+/// `Host_Log` at `0x1` and `Host_Count` at `0x2`. Panics after emitting the diagnostics if it does
+/// not compile.
+pub fn compile(src: &str) -> DatFile {
+    let options = CompileOptions {
+        externs: ExternMap::parse("Host_Log = 0x1\nHost_Count = 0x2").unwrap(),
+        ..Default::default()
+    };
+
+    compile_sources(&options, [("test.d", src)])
+        .unwrap_or_else(|diagnostics| {
+            diagnostics.emit();
+            panic!("{} compile error(s)", diagnostics.errors.len());
+        })
+        .output
+}
@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+
+use daedalus_parser::{
+    AssocOp, Block, BlockItem, Expr, ExprKind, FunctionCall, Ident, IfStatement, LitKind,
+    ReturnStatement, UnaryOp, Var, VarKind,
+};
+use dat_file::properties::DataType;
+
+use crate::{
+    error::CompileError,
+    files::{File, FileId},
+    symbol_indices::{self, SymbolIndices, SymbolKind},
+};
+
+/// Argument and return types of a function
+struct Signature {
+    args: Vec<DataType>,
+    ret: DataType,
+}
+
+/// Checks types of all expressions, calls, assignments and returns
+///
+/// Expressions whose type can not be inferred (unknown identifiers and such) are not reported,
+/// the compiler reports the root cause on its own
+pub struct TypeChecker<'a> {
+    symbol_indices: &'a SymbolIndices,
+    signatures: &'a HashMap<String, Signature>,
+    errors: &'a mut Vec<CompileError>,
+
+    file: FileId,
+    /// Same as [`crate::block_builder::BlockBuilder::scope`]
+    scope: String,
+    /// Whether `self` refers to the instance that is being initialized
+    has_this: bool,
+    /// Return type of the function that is being checked
+    ret: DataType,
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn check<'f>(
+        files: impl IntoIterator<Item = &'f File> + Clone,
+        symbol_indices: &SymbolIndices,
+        errors: &mut Vec<CompileError>,
+    ) {
+        let mut signatures = HashMap::new();
+        for file in files.clone() {
+            for item in file.ast.items.iter() {
+                let (ident, ty, args) = match item {
+                    daedalus_parser::Item::Func(func) => (&func.ident, &func.ty, &func.args),
+                    daedalus_parser::Item::ExternFunc(func) => (&func.ident, &func.ty, &func.args),
+                    _ => continue,
+                };
+
+                signatures.insert(
+                    ident.raw.to_uppercase(),
+                    Signature {
+                        args: args
+                            .iter()
+                            .map(|var| symbol_indices::data_type(&var.ty.raw))
+                            .collect(),
+                        ret: symbol_indices::data_type(&ty.raw),
+                    },
+                );
+            }
+        }
+
+        for file in files {
+            let mut this = TypeChecker {
+                symbol_indices,
+                signatures: &signatures,
+                errors: &mut *errors,
+                file: file.id,
+                scope: String::new(),
+                has_this: false,
+                ret: DataType::Void,
+            };
+
+            for item in file.ast.items.iter() {
+                this.visit_item(item);
+            }
+        }
+    }
+
+    fn visit_item(&mut self, item: &daedalus_parser::Item) {
+        match item {
+            daedalus_parser::Item::Func(func) => {
+                self.scope = func.ident.raw.to_uppercase();
+                self.has_this = false;
+                self.ret = symbol_indices::data_type(&func.ty.raw);
+                self.visit_block(&func.block);
+            }
+            daedalus_parser::Item::Instance(instance) => {
+                let parent = instance.parent.raw.to_uppercase();
+                self.scope = self.symbol_indices.parent_class(&parent).to_string();
+                self.has_this = true;
+                self.ret = DataType::Void;
+                self.visit_block(&instance.block);
+            }
+            daedalus_parser::Item::Prototype(prototype) => {
+                self.scope = prototype.parent.raw.to_uppercase();
+                self.has_this = true;
+                self.ret = DataType::Void;
+                self.visit_block(&prototype.block);
+            }
+            daedalus_parser::Item::Const(item) => {
+                self.scope = String::new();
+                self.has_this = false;
+
+                let ty = symbol_indices::data_type(&item.ty.raw);
+                match &item.kind {
+                    daedalus_parser::ConstKind::Value { init } => self.expect(ty, init),
+                    daedalus_parser::ConstKind::Array { init, .. } => {
+                        for init in init.iter() {
+                            self.expect(ty, init);
+                        }
+                    }
+                }
+            }
+            daedalus_parser::Item::Class(_)
+            | daedalus_parser::Item::Var(_)
+            | daedalus_parser::Item::ExternFunc(_) => {}
+        }
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        for item in block.items.iter() {
+            match item {
+                BlockItem::Expr(expr) => {
+                    self.visit_expr(expr);
+                }
+                BlockItem::Var(var) => self.visit_local(var),
+                BlockItem::Return(ret) => self.visit_return(ret),
+                BlockItem::If(stmt) => self.visit_if(stmt),
+            }
+        }
+    }
+
+    fn visit_local(&mut self, var: &Var) {
+        let ty = symbol_indices::data_type(&var.ty.raw);
+        match &var.kind {
+            VarKind::Value { init: Some(init) } => self.expect(ty, init),
+            VarKind::Array {
+                init: Some(init), ..
+            } => {
+                for init in init.iter() {
+                    self.expect(ty, init);
+                }
+            }
+            VarKind::Value { init: None } | VarKind::Array { init: None, .. } => {}
+        }
+    }
+
+    fn visit_return(&mut self, ret: &ReturnStatement) {
+        match (self.ret, &ret.expr) {
+            (DataType::Void, Some(expr)) => {
+                self.errors.push(CompileError::UnexpectedReturnValue {
+                    file: self.file,
+                    span: expr.span.clone(),
+                });
+            }
+            (DataType::Void, None) => {}
+            (ty, None) => {
+                self.errors.push(CompileError::MissingReturnValue {
+                    expected: ty,
+                    file: self.file,
+                    span: ret.span.clone(),
+                });
+            }
+            (ty, Some(expr)) => self.expect(ty, expr),
+        }
+    }
+
+    fn visit_if(&mut self, stmt: &IfStatement) {
+        let mut stmt = Some(stmt);
+        while let Some(branch) = stmt {
+            if let Some(condition) = branch.condition.as_ref() {
+                self.expect(DataType::Int, condition);
+            }
+
+            self.visit_block(&branch.block);
+            stmt = branch.next.as_deref();
+        }
+    }
+
+    /// Type of the value an expression leaves on the stack
+    fn visit_expr(&mut self, expr: &Expr) -> Option<DataType> {
+        match &expr.kind {
+            ExprKind::Lit(lit) => Some(match lit.kind {
+                LitKind::Intager(_) => DataType::Int,
+                LitKind::Float(_) => DataType::Float,
+                LitKind::String(_) => DataType::String,
+            }),
+            ExprKind::Ident(ident) => self.visit_reference(ident),
+            // Indices are validated by the compiler, they have to be constant
+            ExprKind::Index(expr, _) => self.visit_expr(expr),
+            ExprKind::Paren(expr) => self.visit_expr(expr),
+            ExprKind::Unary(_, expr) => {
+                self.expect(DataType::Int, expr);
+                Some(DataType::Int)
+            }
+            ExprKind::Binary(op, left, right) => match op {
+                AssocOp::Assign => {
+                    if let Some(ty) = self.visit_expr(left) {
+                        self.expect(ty, right);
+                    }
+                    None
+                }
+                AssocOp::AddAssign
+                | AssocOp::SubtractAssign
+                | AssocOp::MultiplyAssign
+                | AssocOp::DivideAssign => {
+                    self.expect(DataType::Int, left);
+                    self.expect(DataType::Int, right);
+                    None
+                }
+                _ => {
+                    self.expect(DataType::Int, left);
+                    self.expect(DataType::Int, right);
+                    Some(DataType::Int)
+                }
+            },
+            ExprKind::Call(call) => self.visit_call(call),
+            ExprKind::Field(base, field) => self.visit_field(base, field),
+        }
+    }
+
+    fn visit_reference(&self, ident: &Ident) -> Option<DataType> {
+        let name = ident.raw.to_uppercase();
+
+        if self.has_this && matches!(name.as_str(), "SELF" | "THIS") {
+            return Some(DataType::Instance);
+        }
+
+        let symbol = self
+            .symbol_indices
+            .get(&format!("{}.{name}", self.scope))
+            .or_else(|| self.symbol_indices.get(&name))?;
+
+        match symbol.kind {
+            SymbolKind::Function | SymbolKind::ExternFunction => Some(DataType::Func),
+            SymbolKind::Instance => Some(DataType::Instance),
+            SymbolKind::Other => match symbol.ty {
+                DataType::Class => None,
+                // Prototypes can only be used as instance parents
+                DataType::Prototype => None,
+                ty => Some(ty),
+            },
+        }
+    }
+
+    fn visit_field(&mut self, base: &Expr, field: &Ident) -> Option<DataType> {
+        let ExprKind::Ident(ident) = &base.kind else {
+            self.visit_expr(base);
+            return None;
+        };
+
+        let name = ident.raw.to_uppercase();
+        let scoped = format!("{}.{name}", self.scope);
+
+        let class = if self.has_this && matches!(name.as_str(), "SELF" | "THIS") {
+            Some(self.scope.as_str())
+        } else if self.symbol_indices.contains_key(&scoped) {
+            self.symbol_indices.instance_class(&scoped)
+        } else {
+            self.symbol_indices.instance_class(&name)
+        }?;
+
+        self.symbol_indices
+            .get(&format!("{class}.{}", field.raw.to_uppercase()))
+            .map(|symbol| symbol.ty)
+    }
+
+    fn visit_call(&mut self, call: &FunctionCall) -> Option<DataType> {
+        let Some(signature) = self.signatures.get(&call.ident.raw.to_uppercase()) else {
+            for arg in call.args.iter() {
+                self.visit_expr(arg);
+            }
+            return None;
+        };
+
+        let ret = signature.ret;
+        let args = &signature.args;
+
+        if args.len() != call.args.len() {
+            self.errors.push(CompileError::ArgumentCount {
+                ident: call.ident.raw.clone(),
+                expected: args.len(),
+                found: call.args.len(),
+                file: self.file,
+                span: call.ident.span.clone(),
+            });
+        }
+
+        for (&ty, arg) in args.iter().zip(call.args.iter()) {
+            self.expect(ty, arg);
+        }
+
+        Some(ret)
+    }
+
+    fn expect(&mut self, expected: DataType, expr: &Expr) {
+        let Some(found) = self.visit_expr(expr) else {
+            return;
+        };
+
+        // Scripts write whole numbers where floats are expected, as in
+        // `Mdl_SetModelFatness(self, 2)`, the compiler turns such literals into floats
+        let is_float_literal = expected == DataType::Float && int_literal(expr).is_some();
+
+        if !is_assignable(expected, found) && !is_float_literal {
+            self.errors.push(CompileError::TypeMismatch {
+                expected,
+                found,
+                file: self.file,
+                span: expr.span.clone(),
+            });
+        }
+    }
+}
+
+/// Value of an int literal, optionally negated, e.g. `2` or `-2`
+pub fn int_literal(expr: &Expr) -> Option<i32> {
+    match &expr.kind {
+        ExprKind::Lit(lit) => match lit.kind {
+            LitKind::Intager(v) => Some(v),
+            _ => None,
+        },
+        ExprKind::Unary(UnaryOp::Negative, expr) => int_literal(expr).map(i32::wrapping_neg),
+        ExprKind::Paren(expr) => int_literal(expr),
+        _ => None,
+    }
+}
+
+fn is_assignable(expected: DataType, found: DataType) -> bool {
+    match (expected, found) {
+        (expected, found) if expected == found => true,
+        // Engine refers to instances and functions by their symbol index, so it is quite common to
+        // pass them to externs as ints
+        (DataType::Int, DataType::Instance | DataType::Func) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::Files;
+    use indoc::indoc;
+
+    fn check(src: &str) -> Vec<(String, &str)> {
+        let mut files_store = Files::new();
+        let files = [files_store.parse("test.d", src).unwrap()];
+
+        let indices = SymbolIndices::build(&files);
+        let mut errors = Vec::new();
+        TypeChecker::check(&files, &indices, &mut errors);
+
+        errors
+            .iter()
+            .map(|err| (err.to_string(), &src[err.span().clone()]))
+            .collect()
+    }
+
+    #[test]
+    fn valid() {
+        let errors = check(indoc! {r#"
+        class C_NPC { var int id; var string name; };
+        instance hero(C_NPC) {
+            name = "Hero";
+            id = 5;
+        };
+        func void take(var int id, var func f, var C_NPC npc) {};
+        extern func void Mdl_SetModelFatness(var C_NPC npc, var float fatness)
+        func int answer() {
+            var string s;
+            var float f;
+            s = "a";
+            f = 1;
+            f = -(2);
+            take(hero, answer, hero);
+            Mdl_SetModelFatness(hero, 2);
+            Mdl_SetModelFatness(hero, -1);
+            if answer() == 42 {
+                return answer() + 1;
+            };
+            return -1;
+        };
+        "#});
+
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn mismatches() {
+        let errors = check(indoc! {r#"
+        func void take(var int a, var string b) {};
+        func void nothing() {
+            return 1;
+        };
+        func int answer() {
+            var float f;
+            f = 1;
+            f = answer();
+            take("a", "b");
+            take(1);
+            if "yes" { };
+            return;
+        };
+        "#});
+
+        assert_eq!(
+            errors,
+            [
+                ("a void function can not return a value".into(), "1"),
+                (
+                    "mismatched types: expected `float`, found `int`".into(),
+                    "answer()"
+                ),
+                (
+                    "mismatched types: expected `int`, found `string`".into(),
+                    "\"a\""
+                ),
+                (
+                    "function `take` takes 2 argument(s), but 1 were supplied".into(),
+                    "take"
+                ),
+                (
+                    "mismatched types: expected `int`, found `string`".into(),
+                    "\"yes\""
+                ),
+                ("missing return value of type `int`".into(), "return;"),
+            ]
+        );
+    }
+}
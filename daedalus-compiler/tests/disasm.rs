@@ -0,0 +1,136 @@
+use daedalus_compiler::test_support::compile;
+use dat_file::{disasm::Disassembly, DatFile, SymbolData};
+use indoc::indoc;
+
+const SRC: &str = indoc! {r#"
+        class C_Test { var int attribute[2]; };
+        prototype Test_Default(C_Test) { attribute[1] = 10; };
+        instance hero(Test_Default) {};
+
+        extern func void PrintDebug(var string s)
+
+        func int sign(var int v) {
+            if v < 0 {
+                return -1;
+            } else if v == 0 {
+                return 0;
+            };
+            return 1;
+        };
+
+        func void log() {
+            PrintDebug("hello");
+            hero.attribute[1] = sign(2);
+        };
+        "#};
+
+#[test]
+fn listing() {
+    let dat = compile(SRC);
+
+    let disassembly = Disassembly::new(&dat);
+    assert_eq!(disassembly.blocks.len(), 4);
+    assert_eq!(disassembly.labels.len(), 2);
+
+    assert_eq!(
+        disassembly.to_string(),
+        indoc! {r#"
+        prototype TEST_DEFAULT:
+          00000000  PushInt         10
+          00000005  PushVV          C_TEST.ATTRIBUTE[1]
+          0000000b  MovInt
+          0000000c  Return
+
+        instance HERO:
+          0000000d  Call            TEST_DEFAULT
+          00000012  Return
+
+        func SIGN:
+          00000013  PushVar         SIGN.V
+          00000018  MovInt
+          00000019  PushInt         0
+          0000001e  PushVar         SIGN.V
+          00000023  Lt
+          00000024  Bz              label_0
+          00000029  PushInt         1
+          0000002e  Negate
+          0000002f  Return
+          00000030  B               label_1
+        label_0:
+          00000035  PushInt         0
+          0000003a  PushVar         SIGN.V
+          0000003f  Eq
+          00000040  Bz              label_1
+          00000045  PushInt         0
+          0000004a  Return
+        label_1:
+          0000004b  PushInt         1
+          00000050  Return
+          00000051  Return
+
+        func LOG:
+          00000052  PushVar         $10000 ; "hello"
+          00000057  CallExtern      PRINTDEBUG
+          0000005c  PushInt         2
+          00000061  Call            SIGN
+          00000066  GMovI           HERO
+          0000006b  PushVV          C_TEST.ATTRIBUTE[1]
+          00000071  MovInt
+          00000072  Return
+        "#}
+    );
+}
+
+#[test]
+fn assemble_roundtrip() {
+    let dat = compile(SRC);
+
+    let mut assembled = compile(SRC);
+    assembled
+        .assemble(&Disassembly::new(&dat).to_string())
+        .unwrap();
+
+    let encode = |dat: &DatFile| {
+        let mut out = Vec::new();
+        dat.encode(&mut out).unwrap();
+        out
+    };
+    assert_eq!(encode(&assembled), encode(&dat));
+}
+
+#[test]
+fn assemble_patch() {
+    let mut dat = compile(SRC);
+
+    // Make `sign` always return 1 and shift everything after it
+    let listing = Disassembly::new(&dat)
+        .to_string()
+        .replace("func SIGN:\n", "func SIGN:\n  PushInt 1\n  Return\n");
+    dat.assemble(&listing).unwrap();
+
+    let address = |name: &[u8]| {
+        let symbol = dat
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name.as_deref().map(|n| &n[..]) == Some(name))
+            .unwrap();
+        match symbol.data {
+            SymbolData::Address(address) => address,
+            _ => unreachable!(),
+        }
+    };
+    assert_eq!(address(b"SIGN"), 0x13);
+    assert_eq!(address(b"LOG"), 0x52 + 6);
+
+    let err = dat.assemble("Call MISSING").unwrap_err();
+    assert_eq!(err.to_string(), "line 1: unknown symbol `MISSING`");
+
+    // Headers only make sense for symbols with code
+    let err = dat.assemble("func PRINTDEBUG:\n  Return").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "line 1: `PRINTDEBUG` is not a function, instance or prototype with code"
+    );
+    let err = dat.assemble("func #9999:\n  Return").unwrap_err();
+    assert_eq!(err.to_string(), "line 1: unknown symbol `#9999`");
+}
@@ -0,0 +1,125 @@
+//! Compares compiled scripts with DAT files produced by the original compiler, run with
+//! `cargo test -p daedalus-compiler --test golden -- --ignored --nocapture` to see how far from
+//! byte-exact the output is
+
+use std::io::Cursor;
+
+use daedalus_compiler::{compile_sources, test_support::compile, CompileOptions};
+use dat_file::{
+    diff::{Count, DatDiff, SymbolKind},
+    DatFile,
+};
+use indoc::indoc;
+
+#[test]
+fn diff_counts() {
+    let reference = compile(indoc! {r#"
+    const int A = 1;
+    func int f() { return 1; };
+    "#});
+    let dat = compile(indoc! {r#"
+    const int A = 2;
+    func int f() { return 2; };
+    "#});
+
+    let diff = DatDiff::new(&reference, &reference);
+    assert!(diff.is_identical(), "{diff}");
+
+    let diff = DatDiff::new(&dat, &reference);
+    assert!(!diff.is_identical());
+    assert_eq!(
+        diff.symbols.get(&SymbolKind::Const),
+        Some(&Count {
+            total: 1,
+            mismatched: 1
+        })
+    );
+    assert_eq!(
+        diff.symbols.get(&SymbolKind::Func),
+        Some(&Count {
+            total: 1,
+            mismatched: 0
+        })
+    );
+    assert_eq!(
+        diff.instructions,
+        Count {
+            total: 3,
+            mismatched: 1
+        }
+    );
+
+    assert_eq!((diff.index_drift, diff.address_drift), (0, 0));
+
+    // Everything only the compiled file has is a mismatch too, `f` moving to another index and
+    // address doesn't stop it from being compared with its counterpart. The comment keeps `f` at
+    // the same source position, which is compared.
+    let reference = compile(indoc! {r#"
+    // func void g();
+    func int f() { return 1; };
+    "#});
+    let dat = compile(indoc! {r#"
+    func void g() {};
+    func int f() { return 1; };
+    "#});
+
+    let diff = DatDiff::new(&dat, &reference);
+    assert_eq!(
+        diff.symbols.get(&SymbolKind::Func),
+        Some(&Count {
+            total: 2,
+            mismatched: 1
+        })
+    );
+    assert_eq!(diff.symbol_mismatches.len(), 1);
+    assert_eq!(diff.symbol_mismatches[0].name, "G");
+    assert_eq!((diff.index_drift, diff.address_drift), (1, 1));
+    assert_eq!(
+        diff.instructions,
+        Count {
+            total: 4,
+            mismatched: 1
+        }
+    );
+    assert!(diff
+        .to_string()
+        .contains("drift: 1 symbol(s) at another index, 1 with code at another address\n"));
+}
+
+// TODO: Don't want to commit the scripts and a few MB .dat file in the repo
+#[ignore]
+#[test]
+fn golden_gothic2_nor() {
+    let base_path = "../test_data/G2MDK-PolishScripts/Content/";
+    let src = src_file::load(format!("{base_path}Gothic.src"));
+
+    let sources: Vec<(String, String)> = src
+        .iter()
+        .map(|path| {
+            let bytes = std::fs::read(path).unwrap();
+            let (src, _, _) = encoding_rs::WINDOWS_1250.decode(&bytes);
+            (path.to_string_lossy().into_owned(), src.into_owned())
+        })
+        .collect();
+
+    let options = CompileOptions {
+        code_span_compat: true,
+        ..Default::default()
+    };
+    let compiled = compile_sources(
+        &options,
+        sources
+            .iter()
+            .map(|(path, src)| (path.as_str(), src.as_str())),
+    )
+    .unwrap_or_else(|diagnostics| {
+        diagnostics.emit();
+        panic!("{} compile error(s)", diagnostics.errors.len());
+    });
+
+    let reference = std::fs::read("../test_data/gothic_g2nor.dat").unwrap();
+    let reference = DatFile::decode(&mut Cursor::new(reference)).unwrap();
+
+    let diff = DatDiff::new(&compiled.output, &reference);
+    println!("{diff}");
+}
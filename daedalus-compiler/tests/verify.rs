@@ -0,0 +1,141 @@
+use daedalus_bytecode::{cfg::CfgError, Bytecode, Opcode};
+use daedalus_compiler::test_support::compile;
+use dat_file::{
+    properties::DataType,
+    verify::{StackType, VerifyError},
+};
+use indoc::indoc;
+
+#[test]
+fn compiled_code() {
+    let dat = compile(indoc! {r#"
+        class C_Test { var int attribute[2]; var string name; };
+        prototype Test_Default(C_Test) { attribute[1] = 10; name = "default"; };
+        instance hero(Test_Default) { self.attribute[0] = 5; };
+
+        extern func void PrintDebug(var string s)
+        extern func string IntToString(var int x)
+
+        func int sign(var int v) {
+            if v < 0 {
+                return -1;
+            } else if v == 0 {
+                return 0;
+            };
+            return 1;
+        };
+
+        func void log(var C_Test npc, var int v) {
+            PrintDebug(IntToString(sign(v)));
+            sign(v);
+            hero.attribute[1] += npc.attribute[0] * 2;
+            npc.name = hero.name;
+        };
+        "#});
+
+    assert_eq!(dat.verify(), []);
+}
+
+/// Single function `COUNT` with its code replaced by `asm`
+fn verify(asm: &str) -> Vec<VerifyError> {
+    let mut dat = compile(indoc! {"
+        var string name;
+        func int count() { return 1; };
+        "});
+    dat.assemble(&format!("func COUNT:\n{asm}")).unwrap();
+
+    dat.verify().into_iter().map(|(_, err)| err).collect()
+}
+
+#[test]
+fn errors() {
+    assert_eq!(verify("PushInt 1\nReturn"), []);
+
+    assert_eq!(
+        verify("PushInt 1\nAdd\nReturn"),
+        [VerifyError::StackUnderflow(5)]
+    );
+    assert_eq!(
+        verify(indoc! {"
+            PushInt 1
+            Bz      end
+            PushInt 2
+        end:
+            PushInt 3
+            Return
+        "}),
+        [VerifyError::StackHeightMismatch {
+            address: 15,
+            expected: 0,
+            found: 1,
+        }]
+    );
+    assert_eq!(
+        verify(indoc! {"
+            PushInt 1
+            Bz      other
+            PushInt 2
+            B       end
+        other:
+            PushVar NAME
+        end:
+            PushInt 3
+            Return
+        "}),
+        [VerifyError::StackTypeMismatch {
+            address: 25,
+            expected: StackType::Reference(DataType::String),
+            found: StackType::Int,
+        }]
+    );
+    assert_eq!(
+        verify("PushInt 1\nB 0x2\nReturn"),
+        [VerifyError::Cfg(CfgError::MisalignedJump {
+            from: 5,
+            to: 2
+        })]
+    );
+    assert_eq!(
+        verify("PushVar NAME\nReturn"),
+        [VerifyError::TypeMismatch {
+            address: 5,
+            opcode: Opcode::Return,
+            expected: "an int",
+            found: StackType::Reference(DataType::String),
+        }]
+    );
+    assert_eq!(
+        verify("PushInt 1\nBz end\nPushInt 1\nReturn\nend:\nReturn"),
+        [VerifyError::MissingReturnValue {
+            address: 16,
+            expected: DataType::Int,
+        }]
+    );
+    assert_eq!(
+        verify("PushInt 1\nPushVar NAME\nMovS\nPushInt 1\nReturn")
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        ["`MovS` at 0x0000000a expects a string, found Int"]
+    );
+}
+
+#[test]
+fn invalid_opcode() {
+    let mut dat = compile("func int count() { return 1; };");
+    let mut code = dat.bytecode.as_bytes().to_vec();
+    // `PushInt 1` of `return 1`
+    code[0] = 0xEE;
+    dat.bytecode = decode_bytecode(&code);
+
+    assert_eq!(
+        dat.verify(),
+        [(1, VerifyError::Cfg(CfgError::InvalidInstruction(0)))]
+    );
+}
+
+fn decode_bytecode(code: &[u8]) -> Bytecode {
+    let mut data = (code.len() as u32).to_le_bytes().to_vec();
+    data.extend_from_slice(code);
+    Bytecode::decode(&data[..]).unwrap()
+}
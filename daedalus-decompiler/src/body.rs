@@ -0,0 +1,453 @@
+use std::ops::Range;
+
+use daedalus_bytecode::{Instruction, InstructionData, Opcode};
+use daedalus_parser::{
+    AssocOp, Block, BlockItem, Expr, ExprKind, FunctionCall, IfStatement, Lit, LitKind,
+    ReturnStatement, UnaryOp,
+};
+use dat_file::properties::{DataType, PropFlag};
+
+use crate::{expr, float, ident, int, lit, Code, DecompileError, Decompiler};
+
+/// Lifts the code of a single function, instance or prototype back into statements
+///
+/// Expressions are rebuilt by running the code on a stack of expressions instead of values, a
+/// statement ends once something consumes the stack, like a move or a call of a void function
+pub(crate) struct Body<'d, 'a> {
+    decompiler: &'d Decompiler<'a>,
+    /// Owner of the code, for errors
+    name: String,
+    code: &'d Code,
+    /// Address right after the last instruction
+    end: u32,
+    /// Prefix of parameters and locals, only set in function bodies
+    scope: Option<String>,
+    /// Instance or prototype that is being initialized, `None` in function bodies
+    this: Option<u32>,
+    ret: DataType,
+}
+
+impl<'d, 'a> Body<'d, 'a> {
+    pub fn new(decompiler: &'d Decompiler<'a>, name: String, code: &'d Code, end: u32) -> Self {
+        Self {
+            decompiler,
+            name,
+            code,
+            end,
+            scope: None,
+            this: None,
+            ret: DataType::Void,
+        }
+    }
+
+    pub fn with_scope(self, scope: String) -> Self {
+        Self {
+            scope: Some(scope),
+            ..self
+        }
+    }
+
+    pub fn with_this(self, this: u32) -> Self {
+        Self {
+            this: Some(this),
+            ..self
+        }
+    }
+
+    pub fn with_return(self, ret: DataType) -> Self {
+        Self { ret, ..self }
+    }
+
+    /// Statements of the code, starting at the instruction `skip`
+    pub fn decompile(&self, skip: usize) -> Result<Vec<BlockItem>, DecompileError> {
+        let mut items = self.block(skip..self.code.len())?;
+
+        // Epilogue the compiler appends to every body
+        if let Some(BlockItem::Return(ReturnStatement { expr: None, .. })) = items.last() {
+            items.pop();
+        }
+
+        Ok(items)
+    }
+
+    pub fn error(&self, instruction: usize, reason: &'static str) -> DecompileError {
+        DecompileError::Code {
+            symbol: self.name.clone(),
+            address: self.address(instruction),
+            reason,
+        }
+    }
+
+    fn address(&self, instruction: usize) -> u32 {
+        self.code
+            .get(instruction)
+            .map_or(self.end, |(address, _)| *address)
+    }
+
+    fn block(&self, range: Range<usize>) -> Result<Vec<BlockItem>, DecompileError> {
+        let mut items = Vec::new();
+        let mut stack = Vec::new();
+        // Set by `GMovI`, for the field pushed right after it
+        let mut instance = None;
+
+        let mut i = range.start;
+        while i < range.end {
+            let (_, instruction) = &self.code[i];
+            let at = i;
+            i += 1;
+
+            if let Some(op) = binary_op(instruction.opcode) {
+                // Left operand is on top
+                let left = self.pop(&mut stack, at)?;
+                let right = self.pop(&mut stack, at)?;
+                stack.push(expr(ExprKind::Binary(op, operand(left), operand(right))));
+                continue;
+            }
+
+            match (instruction.opcode, &instruction.data) {
+                (Opcode::Nop, _) => {}
+                (Opcode::PushInt, InstructionData::Immediate(v)) => stack.push(int(*v)),
+                (Opcode::PushVar | Opcode::PushVarInstance, InstructionData::Symbol(symbol)) => {
+                    let value = self.reference(*symbol, &mut instance)?;
+                    stack.push(value);
+                }
+                (Opcode::PushVV, InstructionData::SymbolIndex { symbol, index }) => {
+                    let value = self.reference(*symbol, &mut instance)?;
+                    stack.push(expr(ExprKind::Index(
+                        Box::new(value),
+                        Box::new(int(*index as i32)),
+                    )));
+                }
+                (Opcode::GMovI, InstructionData::Symbol(symbol)) => {
+                    instance = Some(self.reference(*symbol, &mut None)?);
+                }
+                (Opcode::Plus, _) => {}
+                (Opcode::Negate | Opcode::Not, _) => {
+                    let op = if instruction.opcode == Opcode::Negate {
+                        UnaryOp::Negative
+                    } else {
+                        UnaryOp::Not
+                    };
+                    let value = self.pop(&mut stack, at)?;
+                    stack.push(expr(ExprKind::Unary(op, operand(value))));
+                }
+                (
+                    Opcode::MovInt | Opcode::MovF | Opcode::MovS | Opcode::MovVF | Opcode::MovVI,
+                    _,
+                ) => {
+                    let ty = match instruction.opcode {
+                        Opcode::MovF => DataType::Float,
+                        Opcode::MovVF => DataType::Func,
+                        _ => DataType::Int,
+                    };
+                    let target = self.pop(&mut stack, at)?;
+                    let value = self.retype(self.pop(&mut stack, at)?, ty);
+                    flush(&mut stack, &mut items);
+                    items.push(assign(AssocOp::Assign, target, value));
+                }
+                (Opcode::AddMovI | Opcode::SubMovI | Opcode::MulMovI | Opcode::DivMovI, _) => {
+                    let op = match instruction.opcode {
+                        Opcode::AddMovI => AssocOp::AddAssign,
+                        Opcode::SubMovI => AssocOp::SubtractAssign,
+                        Opcode::MulMovI => AssocOp::MultiplyAssign,
+                        _ => AssocOp::DivideAssign,
+                    };
+                    let target = self.pop(&mut stack, at)?;
+                    let value = self.pop(&mut stack, at)?;
+                    flush(&mut stack, &mut items);
+                    items.push(assign(op, target, value));
+                }
+                (Opcode::Call, InstructionData::Address(address)) => {
+                    let function = self
+                        .decompiler
+                        .function_at(*address)
+                        .ok_or_else(|| self.error(at, "calls an address that is not a function"))?;
+                    self.call(at, function, &mut stack, &mut items)?;
+                }
+                (Opcode::CallExtern, InstructionData::Symbol(function)) => {
+                    self.call(at, *function, &mut stack, &mut items)?;
+                }
+                (Opcode::Return, _) => {
+                    let value = match self.ret {
+                        DataType::Void => None,
+                        ty => stack.pop().map(|value| self.retype(value, ty)),
+                    };
+                    flush(&mut stack, &mut items);
+                    items.push(BlockItem::Return(ReturnStatement {
+                        expr: value,
+                        span: 0..0,
+                    }));
+                }
+                (Opcode::Bz, InstructionData::Address(target)) => {
+                    let condition = self.pop(&mut stack, at)?;
+                    flush(&mut stack, &mut items);
+
+                    let (stmt, next) = self.if_statement(at, condition, *target, range.end)?;
+                    items.push(BlockItem::If(stmt));
+                    i = next;
+                }
+                (Opcode::B, _) => return Err(self.error(at, "jumps outside of an if statement")),
+                _ => return Err(self.error(at, "is not supported")),
+            }
+        }
+
+        flush(&mut stack, &mut items);
+        Ok(items)
+    }
+
+    // cond_a
+    // Bz next_1
+    // body_a
+    // B end
+    // next_1:
+    // cond_b
+    // Bz end
+    // body_b
+    // end:
+    //
+    // The chain `BlockBuilder::visit_if` of the compiler emits, a `B end` at the end of a body
+    // means there is an else branch after it
+    fn if_statement(
+        &self,
+        bz: usize,
+        condition: Expr,
+        target: u32,
+        end: usize,
+    ) -> Result<(IfStatement, usize), DecompileError> {
+        let start = bz + 1;
+        let next = self.index_of(bz, target, start..end + 1)?;
+
+        let else_end = match self.code[start..next].last() {
+            Some((
+                _,
+                Instruction {
+                    opcode: Opcode::B,
+                    data: InstructionData::Address(address),
+                },
+            )) if *address > target => Some(self.index_of(next - 1, *address, next..end + 1)?),
+            _ => None,
+        };
+
+        let body_end = if else_end.is_some() { next - 1 } else { next };
+        let block = Block {
+            items: self.block(start..body_end)?,
+        };
+
+        let (next_stmt, resume) = match else_end {
+            Some(else_end) => {
+                let mut items = self.block(next..else_end)?;
+
+                // `else if` is just an else branch holding a single if statement
+                let stmt = match items.as_slice() {
+                    [BlockItem::If(_)] => {
+                        let Some(BlockItem::If(mut stmt)) = items.pop() else {
+                            unreachable!()
+                        };
+                        stmt.has_else = true;
+                        stmt
+                    }
+                    _ => IfStatement {
+                        has_else: true,
+                        has_if: false,
+                        has_semi: true,
+                        block: Block { items },
+                        condition: None,
+                        next: None,
+                    },
+                };
+
+                (Some(Box::new(stmt)), else_end)
+            }
+            None => (None, next),
+        };
+
+        let stmt = IfStatement {
+            has_else: false,
+            has_if: true,
+            has_semi: next_stmt.is_none(),
+            block,
+            condition: Some(condition),
+            next: next_stmt,
+        };
+
+        Ok((stmt, resume))
+    }
+
+    fn pop(&self, stack: &mut Vec<Expr>, at: usize) -> Result<Expr, DecompileError> {
+        stack
+            .pop()
+            .ok_or_else(|| self.error(at, "pops an empty stack"))
+    }
+
+    /// Index of the instruction at `address`, which has to be in `range`
+    fn index_of(
+        &self,
+        jump: usize,
+        address: u32,
+        range: Range<usize>,
+    ) -> Result<usize, DecompileError> {
+        let index = self.code.partition_point(|(at, _)| *at < address);
+
+        if self.address(index) != address || !range.contains(&index) {
+            return Err(self.error(jump, "jumps outside of an if statement"));
+        }
+        Ok(index)
+    }
+
+    fn call(
+        &self,
+        at: usize,
+        function: u32,
+        stack: &mut Vec<Expr>,
+        items: &mut Vec<BlockItem>,
+    ) -> Result<(), DecompileError> {
+        let decompiler = self.decompiler;
+        let symbol = decompiler.symbol(function)?;
+        if symbol.props.elem_props.data_type() != DataType::Func {
+            return Err(self.error(at, "calls a symbol that is not a function"));
+        }
+
+        let params = decompiler.params(function)?;
+        if stack.len() < params.len() {
+            return Err(self.error(at, "pops an empty stack"));
+        }
+
+        let args = stack
+            .split_off(stack.len() - params.len())
+            .into_iter()
+            .zip(params)
+            .map(|(arg, param)| {
+                let ty = decompiler.symbol(param)?.props.elem_props.data_type();
+                Ok(self.retype(arg, ty))
+            })
+            .collect::<Result<_, DecompileError>>()?;
+
+        let call = expr(ExprKind::Call(FunctionCall {
+            ident: ident(decompiler.name(function)?),
+            args,
+        }));
+
+        if symbol.return_type() == DataType::Void {
+            flush(stack, items);
+            items.push(BlockItem::Expr(call));
+        } else {
+            stack.push(call);
+        }
+
+        Ok(())
+    }
+
+    /// Symbol pushed onto the stack, fields pushed right after `GMovI` are accessed through that
+    /// instance
+    fn reference(&self, symbol: u32, instance: &mut Option<Expr>) -> Result<Expr, DecompileError> {
+        let decompiler = self.decompiler;
+
+        if let Some(value) = decompiler.string_literal(symbol) {
+            return Ok(lit(LitKind::String(value)));
+        }
+
+        if self.this == Some(symbol) {
+            return Ok(expr(ExprKind::Ident(ident("self"))));
+        }
+
+        let flags = decompiler.symbol(symbol)?.props.elem_props.flags();
+        if flags.contains(PropFlag::CLASS_VAR) {
+            let field = ident(decompiler.short_name(symbol)?);
+
+            return Ok(expr(match instance.take() {
+                Some(instance) => ExprKind::Field(Box::new(instance), field),
+                None => ExprKind::Ident(field),
+            }));
+        }
+
+        let name = decompiler.name(symbol)?;
+        let name = match &self.scope {
+            Some(scope) => name
+                .strip_prefix(scope.as_str())
+                .unwrap_or(&name)
+                .to_string(),
+            None => name,
+        };
+        Ok(expr(ExprKind::Ident(ident(name))))
+    }
+
+    /// Floats and functions are pushed as plain ints, so literals only get their real type once
+    /// it is known where they end up
+    fn retype(&self, value: Expr, ty: DataType) -> Expr {
+        match (ty, value.kind) {
+            (
+                DataType::Float,
+                ExprKind::Lit(Lit {
+                    kind: LitKind::Intager(v),
+                }),
+            ) => float(f32::from_bits(v as u32)),
+            (DataType::Float, ExprKind::Unary(UnaryOp::Negative, value)) => expr(ExprKind::Unary(
+                UnaryOp::Negative,
+                Box::new(self.retype(*value, ty)),
+            )),
+            (
+                DataType::Func,
+                ExprKind::Lit(Lit {
+                    kind: LitKind::Intager(v),
+                }),
+            ) => match self.function_name(v) {
+                Some(name) => expr(ExprKind::Ident(ident(name))),
+                None => int(v),
+            },
+            (_, kind) => expr(kind),
+        }
+    }
+
+    fn function_name(&self, symbol: i32) -> Option<String> {
+        let symbol = u32::try_from(symbol).ok()?;
+        let is_func = self
+            .decompiler
+            .symbol(symbol)
+            .is_ok_and(|s| s.props.elem_props.data_type() == DataType::Func);
+        is_func.then(|| self.decompiler.name(symbol).ok()).flatten()
+    }
+}
+
+/// Values nobody consumed are results of expression statements, like calls whose return value is
+/// ignored
+fn flush(stack: &mut Vec<Expr>, items: &mut Vec<BlockItem>) {
+    items.extend(stack.drain(..).map(BlockItem::Expr));
+}
+
+fn assign(op: AssocOp, target: Expr, value: Expr) -> BlockItem {
+    BlockItem::Expr(expr(ExprKind::Binary(
+        op,
+        Box::new(target),
+        Box::new(value),
+    )))
+}
+
+/// Nested operators always get parenthesized, so precedence never changes the meaning
+fn operand(value: Expr) -> Box<Expr> {
+    Box::new(match value.kind {
+        ExprKind::Binary(..) => expr(ExprKind::Paren(Box::new(value))),
+        _ => value,
+    })
+}
+
+fn binary_op(opcode: Opcode) -> Option<AssocOp> {
+    Some(match opcode {
+        Opcode::Add => AssocOp::Add,
+        Opcode::Sub => AssocOp::Subtract,
+        Opcode::Mul => AssocOp::Multiply,
+        Opcode::Div => AssocOp::Divide,
+        Opcode::Eq => AssocOp::Equal,
+        Opcode::Neq => AssocOp::NotEqual,
+        Opcode::Lt => AssocOp::Less,
+        Opcode::Lte => AssocOp::LessEqual,
+        Opcode::Gt => AssocOp::Greater,
+        Opcode::Gte => AssocOp::GreaterEqual,
+        Opcode::And => AssocOp::And,
+        Opcode::Orr => AssocOp::Or,
+        Opcode::AndB => AssocOp::BitAnd,
+        Opcode::Or => AssocOp::BitOr,
+        Opcode::Lsl => AssocOp::ShiftLeft,
+        Opcode::Lsr => AssocOp::ShiftRight,
+        _ => return None,
+    })
+}
@@ -0,0 +1,476 @@
+//! Rebuilds Daedalus source from a compiled DAT file
+//!
+//! Symbols turn into items and bytecode is lifted back into statements, compiling the output again
+//! gives the same code. Only the patterns emitted by the compiler, which are the same ones zengin
+//! emits, are understood.
+
+use std::collections::HashMap;
+
+use daedalus_bytecode::{Instruction, InstructionData, InvalidInstruction, Opcode};
+use daedalus_fmt::{DaedalusDisplay as _, DaedalusFormatter};
+use daedalus_parser::{
+    Block, BlockItem, Class, Const, ConstKind, Expr, ExprKind, ExternFunctionDefinition, File,
+    FunctionDefinition, Ident, Instance, Item, Lit, LitKind, Prototype, Ty, Var, VarKind,
+};
+use dat_file::{
+    disasm::{CodeBlock, Disassembly},
+    properties::{DataType, PropFlag},
+    DatFile, Symbol, SymbolData,
+};
+use encoding_rs::Encoding;
+
+mod body;
+use body::Body;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecompileError {
+    #[error("symbol {0} does not exist")]
+    UnknownSymbol(u32),
+    #[error("{symbol}: code at 0x{address:08x} {reason}")]
+    Code {
+        symbol: String,
+        address: u32,
+        reason: &'static str,
+    },
+    #[error(transparent)]
+    InvalidInstruction(#[from] InvalidInstruction),
+}
+
+/// Items of every named symbol in `dat`, in the order of the symbol table
+pub fn decompile(dat: &DatFile, encoding: &'static Encoding) -> Result<File, DecompileError> {
+    let decompiler = Decompiler::new(dat, encoding)?;
+
+    let mut items = Vec::new();
+    for id in 0..dat.symbols.len() as u32 {
+        if let Some(item) = decompiler.item(id)? {
+            items.push(item);
+        }
+    }
+
+    Ok(File { items })
+}
+
+/// `file` printed with [`DaedalusFormatter`]
+pub fn to_source(file: &File) -> String {
+    let mut out = String::new();
+    file.fmt(&mut DaedalusFormatter::new(&mut out))
+        .expect("writing to a string can not fail");
+    out
+}
+
+/// Instructions along with their addresses
+type Code = [(u32, Instruction)];
+
+struct Decompiler<'a> {
+    dat: &'a DatFile,
+    encoding: &'static Encoding,
+    /// Whole bytecode along with the address of every instruction
+    code: Vec<(u32, Instruction)>,
+    /// Sorted by address
+    blocks: Vec<CodeBlock>,
+    /// Classes of instance typed symbols, the DAT does not store them, so they are guessed from
+    /// the fields accessed through the symbol
+    instance_classes: HashMap<u32, u32>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(dat: &'a DatFile, encoding: &'static Encoding) -> Result<Self, DecompileError> {
+        let code = dat
+            .bytecode
+            .decode_range(0..dat.bytecode.as_bytes().len() as u32)?;
+
+        let mut instance_classes = HashMap::new();
+        for pair in code.windows(2) {
+            let [(_, set), (_, field)] = pair else {
+                continue;
+            };
+            let (Opcode::GMovI, InstructionData::Symbol(instance)) = (set.opcode, &set.data) else {
+                continue;
+            };
+            let (InstructionData::Symbol(field)
+            | InstructionData::SymbolIndex { symbol: field, .. }) = &field.data
+            else {
+                continue;
+            };
+
+            if let Some(class) = dat.symbols.get(*field as usize).and_then(|s| s.parent) {
+                instance_classes.insert(*instance, class);
+            }
+        }
+
+        Ok(Self {
+            dat,
+            encoding,
+            code,
+            blocks: Disassembly::new(dat).blocks,
+            instance_classes,
+        })
+    }
+
+    fn symbol(&self, id: u32) -> Result<&'a Symbol, DecompileError> {
+        self.dat
+            .symbols
+            .get(id as usize)
+            .ok_or(DecompileError::UnknownSymbol(id))
+    }
+
+    fn name(&self, id: u32) -> Result<String, DecompileError> {
+        let symbol = self.symbol(id)?;
+        let name = symbol
+            .name
+            .as_ref()
+            .ok_or(DecompileError::UnknownSymbol(id))?;
+        Ok(self.decode(name))
+    }
+
+    /// Name without the `SCOPE.` prefix of fields, parameters and locals
+    fn short_name(&self, id: u32) -> Result<String, DecompileError> {
+        let name = self.name(id)?;
+        Ok(match name.split_once('.') {
+            Some((_, name)) => name.to_string(),
+            None => name,
+        })
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        self.encoding
+            .decode_without_bom_handling(bytes)
+            .0
+            .into_owned()
+    }
+
+    /// Value of a generated string literal symbol
+    fn string_literal(&self, id: u32) -> Option<String> {
+        let symbol = self.dat.symbols.get(id as usize)?;
+        if symbol.name.as_ref()?.first() != Some(&0xFF) {
+            return None;
+        }
+
+        match &symbol.data {
+            SymbolData::String(v) => v.first().map(|v| self.decode(v)),
+            _ => None,
+        }
+    }
+
+    /// Class of instance typed symbols, builtin type name for the rest
+    fn type_name(&self, id: u32) -> Result<String, DecompileError> {
+        let symbol = self.symbol(id)?;
+        match symbol.props.elem_props.data_type() {
+            DataType::Instance => self.instance_type(id),
+            ty => Ok(ty.to_string()),
+        }
+    }
+
+    fn instance_type(&self, id: u32) -> Result<String, DecompileError> {
+        let class = self.symbol(id)?.parent.filter(|parent| {
+            self.dat
+                .symbols
+                .get(*parent as usize)
+                .is_some_and(|parent| parent.props.elem_props.data_type() == DataType::Class)
+        });
+
+        match class.or_else(|| self.instance_classes.get(&id).copied()) {
+            Some(class) => self.name(class),
+            None => Ok("instance".to_string()),
+        }
+    }
+
+    /// Instructions of the function, instance or prototype `id`, and the address right after them
+    fn code_of(&self, id: u32) -> Result<(&Code, u32), DecompileError> {
+        let block = self
+            .blocks
+            .iter()
+            .find(|block| block.symbol == id)
+            .ok_or(DecompileError::UnknownSymbol(id))?;
+
+        let start = self
+            .code
+            .partition_point(|(address, _)| *address < block.start);
+        let end = self
+            .code
+            .partition_point(|(address, _)| *address < block.end);
+        Ok((&self.code[start..end], block.end))
+    }
+
+    /// Function whose code starts at `address`
+    fn function_at(&self, address: u32) -> Option<u32> {
+        let block = self
+            .blocks
+            .binary_search_by_key(&address, |block| block.start)
+            .ok()?;
+        Some(self.blocks[block].symbol)
+    }
+
+    fn item(&self, id: u32) -> Result<Option<Item>, DecompileError> {
+        let symbol = self.symbol(id)?;
+        let Some(name) = symbol.name.as_ref() else {
+            return Ok(None);
+        };
+        // Generated symbols, and the fields, parameters and locals that belong to another item
+        if name.first() == Some(&0xFF) || name.contains(&b'.') {
+            return Ok(None);
+        }
+
+        let props = &symbol.props.elem_props;
+        let flags = props.flags();
+        let is_const = flags.contains(PropFlag::CONST);
+
+        let item = match props.data_type() {
+            DataType::Class => Item::Class(self.class(id)?),
+            DataType::Prototype => Item::Prototype(self.prototype(id)?),
+            DataType::Func if is_const && flags.contains(PropFlag::EXTERNAL) => {
+                Item::ExternFunc(self.extern_func(id)?)
+            }
+            DataType::Func if is_const => Item::Func(self.func(id)?),
+            DataType::Instance if is_const => Item::Instance(self.instance(id)?),
+            DataType::Void => return Ok(None),
+            _ if is_const => match self.constant(id)? {
+                Some(item) => Item::Const(item),
+                None => return Ok(None),
+            },
+            _ => Item::Var(self.var(id)?),
+        };
+
+        Ok(Some(item))
+    }
+
+    fn class(&self, id: u32) -> Result<Class, DecompileError> {
+        let fields = self
+            .dat
+            .symbols
+            .iter()
+            .enumerate()
+            .filter(|(_, symbol)| {
+                symbol.parent == Some(id)
+                    && symbol
+                        .props
+                        .elem_props
+                        .flags()
+                        .contains(PropFlag::CLASS_VAR)
+            })
+            .map(|(field, _)| self.var(field as u32))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Class {
+            ident: ident(self.name(id)?),
+            fields,
+            span: 0..0,
+        })
+    }
+
+    /// Global, field, parameter or local
+    fn var(&self, id: u32) -> Result<Var, DecompileError> {
+        let count = self.symbol(id)?.props.elem_props.count();
+
+        let kind = if count > 1 {
+            VarKind::Array {
+                size_init: int(count as i32),
+                init: None,
+            }
+        } else {
+            VarKind::Value { init: None }
+        };
+
+        Ok(Var {
+            ident: ident(self.short_name(id)?),
+            ty: ty(self.type_name(id)?),
+            kind,
+            span: 0..0,
+        })
+    }
+
+    /// Constants of other types can not be written in the source, `None` for those
+    fn constant(&self, id: u32) -> Result<Option<Const>, DecompileError> {
+        let symbol = self.symbol(id)?;
+
+        let (ty, mut values): (&str, Vec<Expr>) = match &symbol.data {
+            SymbolData::Int(v) => ("int", v.iter().map(|v| int(*v)).collect()),
+            SymbolData::Float(v) => ("float", v.iter().map(|v| float(*v)).collect()),
+            SymbolData::String(v) => (
+                "string",
+                v.iter()
+                    .map(|v| lit(LitKind::String(self.decode(v))))
+                    .collect(),
+            ),
+            _ => return Ok(None),
+        };
+
+        let kind = match values.len() {
+            0 => return Ok(None),
+            1 => ConstKind::Value {
+                init: values.remove(0),
+            },
+            len => ConstKind::Array {
+                size_init: int(len as i32),
+                init: values,
+            },
+        };
+
+        Ok(Some(Const {
+            ident: ident(self.name(id)?),
+            ty: self::ty(ty),
+            kind,
+            span: 0..0,
+        }))
+    }
+
+    /// Parameter symbols directly follow the symbol of the function
+    fn params(&self, id: u32) -> Result<Vec<u32>, DecompileError> {
+        let params = self
+            .dat
+            .params(id)
+            .ok_or(DecompileError::UnknownSymbol(id))?;
+        Ok(params.collect())
+    }
+
+    fn extern_func(&self, id: u32) -> Result<ExternFunctionDefinition, DecompileError> {
+        let symbol = self.symbol(id)?;
+
+        Ok(ExternFunctionDefinition {
+            ident: ident(self.name(id)?),
+            ty: ty(symbol.return_type().to_string()),
+            args: self
+                .params(id)?
+                .into_iter()
+                .map(|param| self.var(param))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn func(&self, id: u32) -> Result<FunctionDefinition, DecompileError> {
+        let symbol = self.symbol(id)?;
+        let name = self.name(id)?;
+        let scope = format!("{name}.");
+        let params = self.params(id)?;
+        let ret = symbol.return_type();
+
+        // Locals come right after the parameters
+        let mut items = Vec::new();
+        for local in id + params.len() as u32 + 1..self.dat.symbols.len() as u32 {
+            if !self.name(local).is_ok_and(|name| name.starts_with(&scope)) {
+                break;
+            }
+            items.push(BlockItem::Var(self.var(local)?));
+        }
+
+        let (code, end) = self.code_of(id)?;
+        let body = Body::new(self, name.clone(), code, end)
+            .with_scope(scope)
+            .with_return(ret);
+
+        // Arguments are popped into their symbols on entry, last one first
+        let mut skip = 0;
+        for param in params.iter().rev() {
+            match code.get(skip..skip + 2) {
+                Some([(_, push), (_, mov)])
+                    if push.data == InstructionData::Symbol(*param) && is_mov(mov.opcode) =>
+                {
+                    skip += 2;
+                }
+                _ => return Err(body.error(skip, "does not pop the arguments")),
+            }
+        }
+
+        items.extend(body.decompile(skip)?);
+
+        Ok(FunctionDefinition {
+            ident: ident(name),
+            ty: ty(ret.to_string()),
+            args: params
+                .into_iter()
+                .map(|param| self.var(param))
+                .collect::<Result<_, _>>()?,
+            block: Block { items },
+            span: 0..0,
+        })
+    }
+
+    fn prototype(&self, id: u32) -> Result<Prototype, DecompileError> {
+        let symbol = self.symbol(id)?;
+        let name = self.name(id)?;
+        let parent = symbol.parent.ok_or(DecompileError::UnknownSymbol(id))?;
+
+        let (code, end) = self.code_of(id)?;
+        let items = Body::new(self, name.clone(), code, end)
+            .with_this(id)
+            .decompile(0)?;
+
+        Ok(Prototype {
+            ident: ident(name),
+            parent: ident(self.name(parent)?),
+            block: Block { items },
+            span: 0..0,
+        })
+    }
+
+    fn instance(&self, id: u32) -> Result<Instance, DecompileError> {
+        let symbol = self.symbol(id)?;
+        let name = self.name(id)?;
+        let parent = symbol.parent.ok_or(DecompileError::UnknownSymbol(id))?;
+
+        let (code, end) = self.code_of(id)?;
+
+        // Prototype defaults are set up by calling it first
+        let parent_address = match self.symbol(parent)?.data {
+            SymbolData::Address(address) => Some(address as u32),
+            _ => None,
+        };
+        let skip = match code.first() {
+            Some((_, call))
+                if call.opcode == Opcode::Call
+                    && parent_address.map(InstructionData::Address) == Some(call.data.clone()) =>
+            {
+                1
+            }
+            _ => 0,
+        };
+
+        let items = Body::new(self, name.clone(), code, end)
+            .with_this(id)
+            .decompile(skip)?;
+
+        Ok(Instance {
+            ident: ident(name),
+            parent: ident(self.name(parent)?),
+            block: Block { items },
+            span: 0..0,
+        })
+    }
+}
+
+fn is_mov(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::MovInt | Opcode::MovF | Opcode::MovS | Opcode::MovVF | Opcode::MovVI
+    )
+}
+
+fn expr(kind: ExprKind) -> Expr {
+    Expr { kind, span: 0..0 }
+}
+
+fn lit(kind: LitKind) -> Expr {
+    expr(ExprKind::Lit(Lit { kind }))
+}
+
+fn int(v: i32) -> Expr {
+    lit(LitKind::Intager(v))
+}
+
+fn float(v: f32) -> Expr {
+    lit(LitKind::Float(v))
+}
+
+fn ident(raw: impl Into<String>) -> Ident {
+    Ident {
+        raw: raw.into(),
+        span: 0..0,
+    }
+}
+
+fn ty(raw: impl Into<String>) -> Ty {
+    Ty {
+        raw: raw.into(),
+        span: 0..0,
+    }
+}
@@ -0,0 +1,50 @@
+use std::{io::Cursor, path::PathBuf, process::exit};
+
+use dat_file::DatFile;
+
+/// Turns a compiled DAT file back into Daedalus source
+#[derive(Debug, clap::Parser)]
+#[command(version)]
+struct Args {
+    /// DAT file to decompile
+    dat: PathBuf,
+    /// Path of the script to write, printed to stdout if not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Encoding of the names and strings in the DAT file, any WHATWG label is accepted
+    #[arg(long, default_value = "windows-1250")]
+    encoding: String,
+}
+
+fn main() {
+    let args = <Args as clap::Parser>::parse();
+
+    let Some(encoding) = encoding_rs::Encoding::for_label(args.encoding.as_bytes()) else {
+        eprintln!("Unknown encoding: {}", args.encoding);
+        exit(1);
+    };
+
+    let dat = std::fs::read(&args.dat)
+        .and_then(|data| DatFile::decode(&mut Cursor::new(data)))
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read {}: {err}", args.dat.display());
+            exit(1);
+        });
+
+    let file = daedalus_decompiler::decompile(&dat, encoding).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        exit(1);
+    });
+    let src = daedalus_decompiler::to_source(&file);
+
+    match args.output {
+        Some(path) => {
+            let (bytes, _, _) = encoding.encode(&src);
+            if let Err(err) = std::fs::write(&path, bytes) {
+                eprintln!("Failed to write {}: {err}", path.display());
+                exit(1);
+            }
+        }
+        None => print!("{src}"),
+    }
+}
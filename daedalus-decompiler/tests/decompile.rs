@@ -0,0 +1,191 @@
+use daedalus_bytecode::Bytecode;
+use daedalus_compiler::test_support::compile;
+use daedalus_decompiler::{decompile, to_source};
+use dat_file::{disasm::Disassembly, DatFile};
+use indoc::indoc;
+use pretty_assertions::assert_eq;
+
+const SRC: &str = indoc! {r#"
+    const int LIMIT = 7;
+    var int counter;
+    var string names[3];
+    var func hook;
+
+    class C_Test {
+        var int attribute[2];
+        var string name;
+        var float speed;
+    };
+
+    prototype Test_Default(C_Test) {
+        attribute[1] = 10;
+        name = "default";
+        speed = 2.0;
+    };
+
+    instance hero(Test_Default) {
+        name = "Hero";
+        self.attribute[0] = -5;
+    };
+
+    extern func void Host_Log(var string msg, var int level)
+    extern func int Host_Count()
+
+    func int clamp(var int v, var int limit) {
+        var int result;
+        if v > limit {
+            result = limit;
+        } else if v < 0 - limit {
+            result = -limit;
+        } else if v == 0 {
+            return 0;
+        } else {
+            result = v;
+        };
+        return result * (2 + LIMIT);
+    };
+
+    func void report(var C_Test npc) {
+        if npc.attribute[0] >= 10 && !(counter == 0) {
+            Host_Log(npc.name, 1);
+        };
+        npc.attribute[1] += Host_Count();
+        npc.speed = 0.5;
+    };
+
+    func void run() {
+        Host_Count();
+        counter = clamp(counter - 1, LIMIT);
+        names[2] = hero.name;
+        hook = run;
+        report(hero);
+    };
+    "#};
+
+fn decompile_source(dat: &DatFile) -> String {
+    to_source(&decompile(dat, encoding_rs::WINDOWS_1250).unwrap())
+}
+
+#[test]
+fn source() {
+    assert_eq!(
+        decompile_source(&compile(SRC)),
+        indoc! {r#"
+        const int LIMIT = 7;
+        var int COUNTER;
+        var string NAMES[3];
+        var func HOOK;
+        class C_TEST {
+            var int ATTRIBUTE[2];
+            var string NAME;
+            var float SPEED;
+        };
+
+        prototype TEST_DEFAULT(C_TEST) {
+            ATTRIBUTE[1] = 10;
+            NAME = "default";
+            SPEED = 2.0;
+        };
+
+        instance HERO(TEST_DEFAULT) {
+            NAME = "Hero";
+            ATTRIBUTE = -5;
+        };
+
+        extern func void HOST_LOG(var string msg, var int level);
+        extern func int HOST_COUNT();
+        func int CLAMP(var int V, var int LIMIT) {
+            var int RESULT;
+            if V > LIMIT {
+                RESULT = LIMIT;
+            } else if V < (0 - LIMIT) {
+                RESULT = -LIMIT;
+            } else if V == 0 {
+                return 0;
+            } else {
+                RESULT = V;
+            };
+            return RESULT * (2 + LIMIT);
+        };
+
+        func void REPORT(var C_TEST NPC) {
+            if (NPC.ATTRIBUTE >= 10) && !(COUNTER == 0) {
+                HOST_LOG(NPC.NAME, 1);
+            };
+            NPC.ATTRIBUTE[1] += HOST_COUNT();
+            NPC.SPEED = 0.5;
+        };
+
+        func void RUN() {
+            HOST_COUNT();
+            COUNTER = CLAMP(COUNTER - 1, LIMIT);
+            NAMES[2] = HERO.NAME;
+            HOOK = RUN;
+            REPORT(HERO);
+        };
+
+        "#}
+    );
+}
+
+#[test]
+fn recompile() {
+    let dat = compile(SRC);
+    let recompiled = compile(&decompile_source(&dat));
+
+    assert_eq!(
+        Disassembly::new(&recompiled).to_string(),
+        Disassembly::new(&dat).to_string()
+    );
+
+    let symbols = |dat: &DatFile| -> Vec<_> {
+        dat.symbols
+            .iter()
+            .map(|symbol| {
+                let props = &symbol.props.elem_props;
+                (
+                    symbol.name.clone(),
+                    props.data_type(),
+                    props.count(),
+                    props.flags().bits(),
+                    symbol.parent,
+                )
+            })
+            .collect()
+    };
+    assert_eq!(symbols(&recompiled), symbols(&dat));
+}
+
+#[test]
+fn loops_are_rejected() {
+    let mut dat = compile("func void spin() {};");
+    dat.assemble(indoc! {"
+        func SPIN:
+        again:
+            PushInt 1
+            Bz again
+            Return
+    "})
+        .unwrap();
+
+    let err = decompile(&dat, encoding_rs::WINDOWS_1250).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "SPIN: code at 0x00000005 jumps outside of an if statement"
+    );
+}
+
+#[test]
+fn invalid_opcode() {
+    let mut dat = compile("func int count() { return 1; };");
+    let mut code = (dat.bytecode.as_bytes().len() as u32)
+        .to_le_bytes()
+        .to_vec();
+    code.extend_from_slice(dat.bytecode.as_bytes());
+    // `PushInt 1` of `return 1`
+    code[4] = 0xEE;
+    dat.bytecode = Bytecode::decode(&code[..]).unwrap();
+
+    let err = decompile(&dat, encoding_rs::WINDOWS_1250).unwrap_err();
+    assert_eq!(err.to_string(), "invalid instruction at 0x00000000");
+}
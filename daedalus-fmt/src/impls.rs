@@ -46,7 +46,7 @@ impl DaedalusDisplay for Block {
 
 impl DaedalusDisplay for Class {
     fn fmt(&self, f: &mut DaedalusFormatter) -> std::fmt::Result {
-        writeln!(f, "class ")?;
+        write!(f, "class ")?;
         self.ident.fmt(f)?;
         writeln!(f, " {{")?;
 
@@ -129,7 +129,13 @@ impl DaedalusDisplay for Expr {
             ExprKind::Lit(Lit {
                 kind: LitKind::Float(lit),
             }) => {
-                write!(f, "{}", lit)?;
+                // Whole numbers would otherwise come back as int literals
+                let lit = lit.to_string();
+                if lit.contains('.') {
+                    write!(f, "{}", lit)?;
+                } else {
+                    write!(f, "{}.0", lit)?;
+                }
             }
             ExprKind::Call(call) => {
                 call.fmt(f)?;
@@ -1,40 +1,40 @@
 use std::{path::Path, process::exit};
 
 use daedalus_fmt as fmt;
-use daedalus_parser::{DaedalusLexer, DaedalusParser, ParseError};
+use daedalus_parser::ParseError;
 
 fn main() {
     let base_path = "./test_data/G2MDK-PolishScripts/Content/";
     let mut src = src_file::load(format!("{base_path}Gothic.src"));
     src.append(&mut src_file::load(format!("{base_path}Fight.src")));
 
-    let len = src.len();
+    let sources: Vec<(&Path, String)> = src
+        .iter()
+        .map(|path| {
+            let bytes = std::fs::read(path).unwrap();
+            let (src, _, _) = encoding_rs::WINDOWS_1250.decode(&bytes);
+            (path.strip_prefix(base_path).unwrap(), src.into_owned())
+        })
+        .collect();
 
-    for (id, path) in src.into_iter().enumerate() {
-        let bytes = std::fs::read(&path).unwrap();
-        let path = path.strip_prefix(base_path).unwrap();
-        println!("{path:?} ({} / {len})", id + 1);
-        parse(path, &bytes);
-    }
-}
-
-fn parse(path: &Path, bytes: &[u8]) {
-    let (src, _, _) = encoding_rs::WINDOWS_1250.decode(bytes);
+    let texts: Vec<&str> = sources.iter().map(|(_, src)| src.as_str()).collect();
+    let files = daedalus_parser::File::parse_many(&texts);
 
-    let mut lexer = DaedalusLexer::new(&src);
-
-    let emit_error = |err: &ParseError| emit_error(path, &src, err);
-    let mut formatter = fmt::DaedalusFormatter::new(fmt::IoFmt(std::io::stdout()));
+    let len = sources.len();
+    for (id, ((path, src), file)) in sources.iter().zip(files).enumerate() {
+        println!("{path:?} ({} / {len})", id + 1);
 
-    let file = match daedalus_parser::File::parse(&mut DaedalusParser { lexer: &mut lexer }) {
-        Ok(file) => file,
-        Err(err) => {
-            emit_error(&err);
-            exit(1);
-        }
-    };
+        let file = match file {
+            Ok(file) => file,
+            Err(err) => {
+                emit_error(path, src, &err);
+                exit(1);
+            }
+        };
 
-    formatter.format(file).unwrap();
+        let mut formatter = fmt::DaedalusFormatter::new(fmt::IoFmt(std::io::stdout()));
+        formatter.format(file).unwrap();
+    }
 }
 
 fn emit_error(path: &Path, src: &str, err: &ParseError) {
@@ -0,0 +1,556 @@
+//! Compact binary form of the AST, used to cache parsed files between compiler runs
+
+use logos::Span;
+
+use crate::{
+    AssocOp, Block, BlockItem, Class, Const, ConstKind, Expr, ExprKind, ExternFunctionDefinition,
+    File, FunctionCall, FunctionDefinition, Ident, IfStatement, Instance, Item, Lit, LitKind,
+    Prototype, ReturnStatement, Ty, UnaryOp, Var, VarKind,
+};
+
+/// Bumped on every change of the AST or of its encoding, so stale caches get rejected
+const VERSION: u32 = 1;
+
+impl File {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Vec::new();
+        VERSION.encode(&mut w);
+        self.items.encode(&mut w);
+        w
+    }
+
+    /// `None` if the data is malformed, or was encoded by a different version
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut r = Reader(bytes);
+        if u32::decode(&mut r)? != VERSION {
+            return None;
+        }
+
+        let items = Vec::decode(&mut r)?;
+        r.0.is_empty().then_some(Self { items })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, tail) = self.0.split_first_chunk()?;
+        self.0 = tail;
+        Some(*head)
+    }
+}
+
+trait Codec: Sized {
+    fn encode(&self, w: &mut Vec<u8>);
+    fn decode(r: &mut Reader) -> Option<Self>;
+}
+
+impl Codec for u32 {
+    fn encode(&self, w: &mut Vec<u8>) {
+        w.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.take().map(u32::from_le_bytes)
+    }
+}
+
+impl Codec for i32 {
+    fn encode(&self, w: &mut Vec<u8>) {
+        w.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.take().map(i32::from_le_bytes)
+    }
+}
+
+impl Codec for f32 {
+    fn encode(&self, w: &mut Vec<u8>) {
+        w.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.take().map(f32::from_le_bytes)
+    }
+}
+
+impl Codec for u8 {
+    fn encode(&self, w: &mut Vec<u8>) {
+        w.push(*self);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.take().map(|[v]| v)
+    }
+}
+
+impl Codec for bool {
+    fn encode(&self, w: &mut Vec<u8>) {
+        (*self as u8).encode(w);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        match u8::decode(r)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Codec for String {
+    fn encode(&self, w: &mut Vec<u8>) {
+        (self.len() as u32).encode(w);
+        w.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        let len = u32::decode(r)? as usize;
+        if r.0.len() < len {
+            return None;
+        }
+
+        let (head, tail) = r.0.split_at(len);
+        r.0 = tail;
+        String::from_utf8(head.to_vec()).ok()
+    }
+}
+
+impl Codec for Span {
+    fn encode(&self, w: &mut Vec<u8>) {
+        (self.start as u32).encode(w);
+        (self.end as u32).encode(w);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(u32::decode(r)? as usize..u32::decode(r)? as usize)
+    }
+}
+
+impl<T: Codec> Codec for Vec<T> {
+    fn encode(&self, w: &mut Vec<u8>) {
+        (self.len() as u32).encode(w);
+        for v in self.iter() {
+            v.encode(w);
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        let len = u32::decode(r)? as usize;
+        // Every element takes at least a byte, so don't trust a length that is longer than that
+        let mut out = Vec::with_capacity(len.min(r.0.len()));
+        for _ in 0..len {
+            out.push(T::decode(r)?);
+        }
+        Some(out)
+    }
+}
+
+impl<T: Codec> Codec for Option<T> {
+    fn encode(&self, w: &mut Vec<u8>) {
+        self.is_some().encode(w);
+        if let Some(v) = self {
+            v.encode(w);
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        if bool::decode(r)? {
+            T::decode(r).map(Some)
+        } else {
+            Some(None)
+        }
+    }
+}
+
+impl<T: Codec> Codec for Box<T> {
+    fn encode(&self, w: &mut Vec<u8>) {
+        (**self).encode(w);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        T::decode(r).map(Box::new)
+    }
+}
+
+/// Structs are encoded field by field, in declaration order
+macro_rules! codec_struct {
+    ($ty:ident { $($field:ident),* }) => {
+        impl Codec for $ty {
+            fn encode(&self, w: &mut Vec<u8>) {
+                $(self.$field.encode(w);)*
+            }
+
+            fn decode(r: &mut Reader) -> Option<Self> {
+                Some(Self {
+                    $($field: Codec::decode(r)?,)*
+                })
+            }
+        }
+    };
+}
+
+codec_struct!(Ident { raw, span });
+codec_struct!(Ty { raw, span });
+codec_struct!(Lit { kind });
+codec_struct!(Expr { kind, span });
+codec_struct!(FunctionCall { ident, args });
+codec_struct!(Var {
+    ident,
+    ty,
+    kind,
+    span
+});
+codec_struct!(Const {
+    ident,
+    ty,
+    kind,
+    span
+});
+codec_struct!(Block { items });
+codec_struct!(ReturnStatement { expr, span });
+codec_struct!(IfStatement {
+    has_else,
+    has_if,
+    has_semi,
+    block,
+    condition,
+    next
+});
+codec_struct!(Class {
+    ident,
+    fields,
+    span
+});
+codec_struct!(Instance {
+    ident,
+    parent,
+    block,
+    span
+});
+codec_struct!(Prototype {
+    ident,
+    parent,
+    block,
+    span
+});
+codec_struct!(FunctionDefinition {
+    ident,
+    ty,
+    args,
+    block,
+    span
+});
+codec_struct!(ExternFunctionDefinition { ident, ty, args });
+
+/// Fieldless enums are encoded as their variant index
+macro_rules! codec_unit_enum {
+    ($ty:ident { $($variant:ident),* }) => {
+        impl Codec for $ty {
+            fn encode(&self, w: &mut Vec<u8>) {
+                const VARIANTS: &[$ty] = &[$($ty::$variant),*];
+                let id = VARIANTS.iter().position(|v| v == self).unwrap();
+                (id as u8).encode(w);
+            }
+
+            fn decode(r: &mut Reader) -> Option<Self> {
+                let id = u8::decode(r)?;
+                [$($ty::$variant),*].into_iter().nth(id as usize)
+            }
+        }
+    };
+}
+
+codec_unit_enum!(AssocOp {
+    Add,
+    Subtract,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    BitAnd,
+    Or,
+    BitOr,
+    Multiply,
+    Divide,
+    ShiftLeft,
+    ShiftRight,
+    Assign,
+    AddAssign,
+    SubtractAssign,
+    MultiplyAssign,
+    DivideAssign
+});
+codec_unit_enum!(UnaryOp { Not, Negative });
+
+impl Codec for LitKind {
+    fn encode(&self, w: &mut Vec<u8>) {
+        match self {
+            LitKind::Intager(v) => {
+                0u8.encode(w);
+                v.encode(w);
+            }
+            LitKind::Float(v) => {
+                1u8.encode(w);
+                v.encode(w);
+            }
+            LitKind::String(v) => {
+                2u8.encode(w);
+                v.encode(w);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(match u8::decode(r)? {
+            0 => LitKind::Intager(Codec::decode(r)?),
+            1 => LitKind::Float(Codec::decode(r)?),
+            2 => LitKind::String(Codec::decode(r)?),
+            _ => return None,
+        })
+    }
+}
+
+impl Codec for ExprKind {
+    fn encode(&self, w: &mut Vec<u8>) {
+        match self {
+            ExprKind::Binary(op, left, right) => {
+                0u8.encode(w);
+                op.encode(w);
+                left.encode(w);
+                right.encode(w);
+            }
+            ExprKind::Unary(op, expr) => {
+                1u8.encode(w);
+                op.encode(w);
+                expr.encode(w);
+            }
+            ExprKind::Lit(lit) => {
+                2u8.encode(w);
+                lit.encode(w);
+            }
+            ExprKind::Call(call) => {
+                3u8.encode(w);
+                call.encode(w);
+            }
+            ExprKind::Ident(ident) => {
+                4u8.encode(w);
+                ident.encode(w);
+            }
+            ExprKind::Paren(expr) => {
+                5u8.encode(w);
+                expr.encode(w);
+            }
+            ExprKind::Field(expr, ident) => {
+                6u8.encode(w);
+                expr.encode(w);
+                ident.encode(w);
+            }
+            ExprKind::Index(expr, index) => {
+                7u8.encode(w);
+                expr.encode(w);
+                index.encode(w);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(match u8::decode(r)? {
+            0 => ExprKind::Binary(Codec::decode(r)?, Codec::decode(r)?, Codec::decode(r)?),
+            1 => ExprKind::Unary(Codec::decode(r)?, Codec::decode(r)?),
+            2 => ExprKind::Lit(Codec::decode(r)?),
+            3 => ExprKind::Call(Codec::decode(r)?),
+            4 => ExprKind::Ident(Codec::decode(r)?),
+            5 => ExprKind::Paren(Codec::decode(r)?),
+            6 => ExprKind::Field(Codec::decode(r)?, Codec::decode(r)?),
+            7 => ExprKind::Index(Codec::decode(r)?, Codec::decode(r)?),
+            _ => return None,
+        })
+    }
+}
+
+impl Codec for VarKind {
+    fn encode(&self, w: &mut Vec<u8>) {
+        match self {
+            VarKind::Value { init } => {
+                0u8.encode(w);
+                init.encode(w);
+            }
+            VarKind::Array { size_init, init } => {
+                1u8.encode(w);
+                size_init.encode(w);
+                init.encode(w);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(match u8::decode(r)? {
+            0 => VarKind::Value {
+                init: Codec::decode(r)?,
+            },
+            1 => VarKind::Array {
+                size_init: Codec::decode(r)?,
+                init: Codec::decode(r)?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl Codec for ConstKind {
+    fn encode(&self, w: &mut Vec<u8>) {
+        match self {
+            ConstKind::Value { init } => {
+                0u8.encode(w);
+                init.encode(w);
+            }
+            ConstKind::Array { size_init, init } => {
+                1u8.encode(w);
+                size_init.encode(w);
+                init.encode(w);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(match u8::decode(r)? {
+            0 => ConstKind::Value {
+                init: Codec::decode(r)?,
+            },
+            1 => ConstKind::Array {
+                size_init: Codec::decode(r)?,
+                init: Codec::decode(r)?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl Codec for BlockItem {
+    fn encode(&self, w: &mut Vec<u8>) {
+        match self {
+            BlockItem::Var(v) => {
+                0u8.encode(w);
+                v.encode(w);
+            }
+            BlockItem::If(v) => {
+                1u8.encode(w);
+                v.encode(w);
+            }
+            BlockItem::Return(v) => {
+                2u8.encode(w);
+                v.encode(w);
+            }
+            BlockItem::Expr(v) => {
+                3u8.encode(w);
+                v.encode(w);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(match u8::decode(r)? {
+            0 => BlockItem::Var(Codec::decode(r)?),
+            1 => BlockItem::If(Codec::decode(r)?),
+            2 => BlockItem::Return(Codec::decode(r)?),
+            3 => BlockItem::Expr(Codec::decode(r)?),
+            _ => return None,
+        })
+    }
+}
+
+impl Codec for Item {
+    fn encode(&self, w: &mut Vec<u8>) {
+        match self {
+            Item::Class(v) => {
+                0u8.encode(w);
+                v.encode(w);
+            }
+            Item::Instance(v) => {
+                1u8.encode(w);
+                v.encode(w);
+            }
+            Item::Prototype(v) => {
+                2u8.encode(w);
+                v.encode(w);
+            }
+            Item::Var(v) => {
+                3u8.encode(w);
+                v.encode(w);
+            }
+            Item::Const(v) => {
+                4u8.encode(w);
+                v.encode(w);
+            }
+            Item::Func(v) => {
+                5u8.encode(w);
+                v.encode(w);
+            }
+            Item::ExternFunc(v) => {
+                6u8.encode(w);
+                v.encode(w);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(match u8::decode(r)? {
+            0 => Item::Class(Codec::decode(r)?),
+            1 => Item::Instance(Codec::decode(r)?),
+            2 => Item::Prototype(Codec::decode(r)?),
+            3 => Item::Var(Codec::decode(r)?),
+            4 => Item::Const(Codec::decode(r)?),
+            5 => Item::Func(Codec::decode(r)?),
+            6 => Item::ExternFunc(Codec::decode(r)?),
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DaedalusLexer, DaedalusParser};
+    use indoc::indoc;
+
+    #[test]
+    fn roundtrip() {
+        let src = indoc! {r#"
+        const int MAX = 2;
+        const string NAMES[MAX] = { "a", "b" };
+        class C_NPC { var int attribute[MAX]; var string name; };
+        prototype Npc_Default(C_NPC) { attribute[0] = -1; };
+        instance hero(Npc_Default) { self.name = "Hero"; };
+        extern func int Hlp_Random(var int max)
+        var func callback;
+        func float f(var int a) {
+            var int b[2];
+            if a >= 1 && !(a == 2) {
+                b[1] = Hlp_Random(a) * 3;
+            } else if other.attribute[1] {
+                return 1.5;
+            } else {
+                return;
+            };
+            return 0.0;
+        };
+        "#};
+
+        let file = File::parse(&mut DaedalusParser {
+            lexer: &mut DaedalusLexer::new(src),
+        })
+        .unwrap();
+
+        let encoded = file.encode();
+        let decoded = File::decode(&encoded).unwrap();
+        assert_eq!(format!("{decoded:?}"), format!("{file:?}"));
+
+        assert!(File::decode(&encoded[..encoded.len() - 1]).is_none());
+    }
+}
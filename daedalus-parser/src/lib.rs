@@ -4,6 +4,7 @@ use std::num::{ParseFloatError, ParseIntError};
 
 pub use daedalus_lexer as lexer;
 pub use daedalus_lexer::DaedalusLexer;
+mod codec;
 mod parse;
 
 use logos::Span;
@@ -1,4 +1,5 @@
 use daedalus_lexer::{DaedalusLexer, Token, TokenError};
+use logos::Span;
 use std::backtrace::Backtrace;
 
 use crate::{DaedalusParser, ParseError};
@@ -194,7 +195,7 @@ impl AssocOp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum UnaryOp {
     /// '!'
     Not,
@@ -234,6 +235,7 @@ pub enum ExprKind {
 #[derive(Debug)]
 pub struct Expr {
     pub kind: ExprKind,
+    pub span: Span,
 }
 
 impl Expr {
@@ -263,8 +265,10 @@ impl Expr {
             let right = Self::parse_without_op(ctx)?;
             let res = Self::parse_with_op(ctx, right, priority + 1)?;
 
+            let span = left.span.start..res.span.end;
             left = Self {
                 kind: ExprKind::Binary(op, Box::new(left), Box::new(res)),
+                span,
             };
         }
 
@@ -273,12 +277,17 @@ impl Expr {
 
     fn parse_without_op(ctx: &mut DaedalusParser) -> Result<Self, ParseError> {
         let mut peek_lexer = ctx.lexer.clone();
-        let expr = match peek_lexer.peek()? {
+        let token = peek_lexer.peek()?;
+        // Whitespace is made of tokens too, so the previous one ends right where this one starts
+        let start = peek_lexer.span().end;
+
+        let expr = match token {
             Token::Bang => {
                 ctx.lexer.eat_token(Token::Bang)?;
                 let expr = Self::parse_without_op(ctx)?;
                 Expr {
                     kind: ExprKind::Unary(UnaryOp::Not, Box::new(expr)),
+                    span: start..ctx.lexer.span().end,
                 }
             }
             Token::Minus => {
@@ -286,6 +295,7 @@ impl Expr {
                 let expr = Self::parse_without_op(ctx)?;
                 Expr {
                     kind: ExprKind::Unary(UnaryOp::Negative, Box::new(expr)),
+                    span: start..ctx.lexer.span().end,
                 }
             }
             Token::String => {
@@ -294,6 +304,7 @@ impl Expr {
                     kind: ExprKind::Lit(Lit {
                         kind: LitKind::String(raw.to_string()),
                     }),
+                    span: start..ctx.lexer.span().end,
                 }
             }
             Token::Integer => {
@@ -307,6 +318,7 @@ impl Expr {
                     kind: ExprKind::Lit(Lit {
                         kind: LitKind::Intager(value),
                     }),
+                    span: start..ctx.lexer.span().end,
                 }
             }
             Token::Float => {
@@ -322,6 +334,7 @@ impl Expr {
                     kind: ExprKind::Lit(Lit {
                         kind: LitKind::Float(value),
                     }),
+                    span: start..ctx.lexer.span().end,
                 }
             }
             Token::Ident => {
@@ -332,12 +345,14 @@ impl Expr {
                         let call = FunctionCall::parse(ctx)?;
                         Expr {
                             kind: ExprKind::Call(call),
+                            span: start..ctx.lexer.span().end,
                         }
                     }
                     _ => {
                         let ident = Ident::parse(ctx)?;
                         Expr {
                             kind: ExprKind::Ident(ident),
+                            span: start..ctx.lexer.span().end,
                         }
                     }
                 };
@@ -350,6 +365,7 @@ impl Expr {
                 ctx.lexer.eat_token(Token::CloseParen)?;
                 Expr {
                     kind: ExprKind::Paren(Box::new(expr)),
+                    span: start..ctx.lexer.span().end,
                 }
             }
             got => {
@@ -388,6 +404,7 @@ impl Expr {
         ctx.lexer.eat_token(Token::CloseBracket)?;
 
         Ok(Expr {
+            span: parent_expr.span.start..ctx.lexer.span().end,
             kind: ExprKind::Index(Box::new(parent_expr), Box::new(index)),
         })
     }
@@ -399,6 +416,7 @@ impl Expr {
         ctx.lexer.eat_token(Token::Dot)?;
         let ident = Ident::parse(ctx)?;
         Ok(Expr {
+            span: parent_expr.span.start..ident.span.end,
             kind: ExprKind::Field(Box::new(parent_expr), ident),
         })
     }
@@ -423,4 +441,22 @@ mod tests {
         .unwrap();
         dbg!(expr);
     }
+
+    #[test]
+    fn spans() {
+        let src = "a[1] + Foo(b).c";
+
+        let expr = Expr::parse(&mut DaedalusParser {
+            lexer: &mut DaedalusLexer::new(src),
+        })
+        .unwrap();
+
+        assert_eq!(expr.span, 0..src.len());
+
+        let ExprKind::Binary(_, left, right) = &expr.kind else {
+            panic!("{expr:?}");
+        };
+        assert_eq!(&src[left.span.clone()], "a[1]");
+        assert_eq!(&src[right.span.clone()], "Foo(b).c");
+    }
 }
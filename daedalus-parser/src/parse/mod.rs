@@ -42,7 +42,9 @@ pub use ty::Ty;
 mod ident;
 pub use ident::Ident;
 
-use crate::{DaedalusParser, ParseError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{DaedalusLexer, DaedalusParser, ParseError};
 
 #[derive(Debug)]
 pub enum Item {
@@ -55,6 +57,7 @@ pub enum Item {
     ExternFunc(ExternFunctionDefinition),
 }
 
+#[derive(Debug)]
 pub struct File {
     pub items: Vec<Item>,
 }
@@ -101,4 +104,70 @@ impl File {
 
         Ok(Self { items })
     }
+
+    /// Parse many source files at once, work is spread across all available cores
+    ///
+    /// Results are in the same order as `sources`
+    pub fn parse_many(sources: &[&str]) -> Vec<Result<Self, ParseError>> {
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(sources.len());
+
+        // Files differ a lot in size, so instead of splitting them into fixed chunks, each thread
+        // picks the next unparsed file once it's done with the previous one
+        let next = AtomicUsize::new(0);
+        let parse_next = || {
+            let mut out = Vec::new();
+            loop {
+                let id = next.fetch_add(1, Ordering::Relaxed);
+                let Some(source) = sources.get(id) else {
+                    break out;
+                };
+
+                let file = File::parse(&mut DaedalusParser {
+                    lexer: &mut DaedalusLexer::new(source),
+                });
+                out.push((id, file));
+            }
+        };
+
+        let mut parsed: Vec<_> = std::thread::scope(|s| {
+            let workers: Vec<_> = (0..threads).map(|_| s.spawn(parse_next)).collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect()
+        });
+
+        parsed.sort_unstable_by_key(|(id, _)| *id);
+        parsed.into_iter().map(|(_, file)| file).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_many() {
+        let sources: Vec<String> = (0..64)
+            .map(|id| match id {
+                13 => "func void broken(".to_string(),
+                id => "var int a;".repeat(id),
+            })
+            .collect();
+        let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+
+        let files = File::parse_many(&sources);
+        assert_eq!(files.len(), sources.len());
+
+        for (id, file) in files.iter().enumerate() {
+            match file {
+                Ok(file) => assert_eq!(file.items.len(), id),
+                Err(_) => assert_eq!(id, 13),
+            }
+        }
+        assert!(files[13].is_err());
+    }
 }
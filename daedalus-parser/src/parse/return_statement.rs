@@ -1,16 +1,19 @@
 use crate::{DaedalusParser, ParseError};
 use daedalus_lexer::Token;
+use logos::Span;
 
 use super::Expr;
 
 #[derive(Debug)]
 pub struct ReturnStatement {
     pub expr: Option<Expr>,
+    pub span: Span,
 }
 
 impl ReturnStatement {
     pub fn parse(ctx: &mut DaedalusParser) -> Result<Self, ParseError> {
         ctx.lexer.eat_token(Token::Return)?;
+        let start = ctx.lexer.span().start;
 
         let expr = if ctx.lexer.peek()? != Token::Semi {
             let expr = Expr::parse(ctx)?;
@@ -20,7 +23,11 @@ impl ReturnStatement {
         };
 
         ctx.lexer.eat_token(Token::Semi)?;
+        let end = ctx.lexer.span().end;
 
-        Ok(Self { expr })
+        Ok(Self {
+            expr,
+            span: start..end,
+        })
     }
 }
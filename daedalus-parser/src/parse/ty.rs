@@ -1,10 +1,12 @@
 use daedalus_lexer::{Token, TokenError};
+use logos::Span;
 
 use crate::{DaedalusParser, ParseError};
 
 #[derive(Debug)]
 pub struct Ty {
     pub raw: String,
+    pub span: Span,
 }
 
 impl Ty {
@@ -14,18 +16,21 @@ impl Ty {
                 let raw = ctx.lexer.eat_token(Token::Ident)?;
                 Ok(Self {
                     raw: raw.to_string(),
+                    span: ctx.lexer.span(),
                 })
             }
             Token::Func => {
                 let raw = ctx.lexer.eat_token(Token::Func)?;
                 Ok(Self {
                     raw: raw.to_string(),
+                    span: ctx.lexer.span(),
                 })
             }
             Token::Instance => {
                 let raw = ctx.lexer.eat_token(Token::Instance)?;
                 Ok(Self {
                     raw: raw.to_string(),
+                    span: ctx.lexer.span(),
                 })
             }
             got => {
@@ -0,0 +1,644 @@
+//! Interpreter of compiled Daedalus bytecode, so script logic can run outside of the game
+
+use std::collections::HashMap;
+
+use daedalus_bytecode::{Instruction, InstructionData, Opcode};
+use dat_file::{
+    properties::{DataType, PropFlag},
+    DatFile, Symbol, SymbolData,
+};
+use num_traits::FromPrimitive as _;
+use zstring::ZString;
+
+mod memory;
+pub use memory::{InstanceId, Reference, Value};
+use memory::{Memory, Storage};
+
+/// Calls nested deeper than this are assumed to be runaway recursion
+const MAX_CALL_DEPTH: usize = 1024;
+
+/// Host implementation of an extern function
+pub trait ExternFunction {
+    /// Arguments are on the stack with the last one on top, they have to be popped and the return
+    /// value, if any, pushed in their place
+    fn call(&mut self, vm: &mut Vm) -> Result<(), VmError>;
+}
+
+impl<F: FnMut(&mut Vm) -> Result<(), VmError>> ExternFunction for F {
+    fn call(&mut self, vm: &mut Vm) -> Result<(), VmError> {
+        self(vm)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VmError {
+    #[error("no symbol named `{0}`")]
+    UnknownName(String),
+    #[error("no symbol with index {0}")]
+    UnknownSymbol(u32),
+    #[error("symbol `{0}` is not a function")]
+    NotAFunction(String),
+    #[error("symbol `{0}` is not an instance")]
+    NotAnInstance(String),
+    #[error("extern function `{0}` is not registered")]
+    UnregisteredExtern(String),
+    #[error("invalid instruction at 0x{0:x}")]
+    InvalidInstruction(u32),
+    #[error("pop from an empty stack")]
+    StackUnderflow,
+    #[error("expected {expected} on the stack, found {found:?}")]
+    TypeMismatch {
+        expected: &'static str,
+        found: Value,
+    },
+    #[error("{0:?} points to no symbol, field or array element")]
+    InvalidReference(Reference),
+    #[error("{reference:?} does not hold {expected}")]
+    WrongType {
+        reference: Reference,
+        expected: &'static str,
+    },
+    #[error("field `{0}` accessed with no current instance")]
+    NoInstance(String),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("call depth exceeded {MAX_CALL_DEPTH}")]
+    CallDepthExceeded,
+}
+
+#[derive(Debug)]
+struct Frame {
+    /// `None` for functions called by the host, returning from them stops execution
+    return_address: Option<u32>,
+}
+
+/// Executes code of a DAT file
+///
+/// Just like in zengin all variables, locals and parameters included, are global symbols, so
+/// recursion overwrites them.
+pub struct Vm {
+    dat: DatFile,
+    names: HashMap<ZString, u32>,
+    memory: Memory,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    externs: HashMap<u32, Box<dyn ExternFunction>>,
+    /// Instance that fields are resolved against, set by `GMovI` and while an instance gets
+    /// initialized
+    current_instance: Option<InstanceId>,
+    pc: u32,
+}
+
+impl Vm {
+    pub fn new(dat: DatFile) -> Self {
+        let names = dat
+            .symbols
+            .iter()
+            .enumerate()
+            .filter_map(|(id, symbol)| Some((symbol.name.clone()?, id as u32)))
+            .collect();
+
+        Self {
+            memory: Memory::new(&dat.symbols),
+            dat,
+            names,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            externs: HashMap::new(),
+            current_instance: None,
+            pc: 0,
+        }
+    }
+
+    pub fn dat(&self) -> &DatFile {
+        &self.dat
+    }
+
+    /// Index of the symbol, names are case insensitive
+    pub fn symbol_id(&self, name: &str) -> Option<u32> {
+        self.names
+            .get(name.to_ascii_uppercase().as_bytes())
+            .copied()
+    }
+
+    fn resolve(&self, name: &str) -> Result<u32, VmError> {
+        self.symbol_id(name)
+            .ok_or_else(|| VmError::UnknownName(name.to_string()))
+    }
+
+    fn symbol(&self, symbol: u32) -> Result<&Symbol, VmError> {
+        self.dat
+            .symbols
+            .get(symbol as usize)
+            .ok_or(VmError::UnknownSymbol(symbol))
+    }
+
+    fn symbol_name(&self, symbol: u32) -> String {
+        self.symbol(symbol)
+            .ok()
+            .and_then(|symbol| symbol.name.as_ref())
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("#{symbol}"))
+    }
+
+    /// Provide the implementation of an extern function declared by the scripts
+    ///
+    /// While running, the function is taken out of the VM, so it can not call itself through
+    /// [`Vm::call`]
+    pub fn register_extern(
+        &mut self,
+        name: &str,
+        function: impl ExternFunction + 'static,
+    ) -> Result<(), VmError> {
+        let symbol = self.resolve(name)?;
+        let props = &self.symbol(symbol)?.props.elem_props;
+
+        if props.data_type() != DataType::Func || !props.flags().contains(PropFlag::EXTERNAL) {
+            return Err(VmError::NotAFunction(name.to_string()));
+        }
+
+        self.externs.insert(symbol, Box::new(function));
+        Ok(())
+    }
+
+    /// Call a script or extern function, arguments have to be pushed beforehand and the return
+    /// value is left on the stack
+    pub fn call(&mut self, name: &str) -> Result<(), VmError> {
+        let symbol = self.resolve(name)?;
+        self.call_symbol(symbol)
+    }
+
+    pub fn call_symbol(&mut self, symbol: u32) -> Result<(), VmError> {
+        let props = &self.symbol(symbol)?.props.elem_props;
+        if props.data_type() != DataType::Func || !props.flags().contains(PropFlag::CONST) {
+            return Err(VmError::NotAFunction(self.symbol_name(symbol)));
+        }
+
+        if props.flags().contains(PropFlag::EXTERNAL) {
+            return self.call_extern(symbol);
+        }
+
+        match self.symbol(symbol)?.data {
+            SymbolData::Address(address) => self.run(address as u32),
+            _ => Err(VmError::NotAFunction(self.symbol_name(symbol))),
+        }
+    }
+
+    /// Create an object of the instance's class and run its initializer, prototype included
+    pub fn init_instance(&mut self, name: &str) -> Result<InstanceId, VmError> {
+        let symbol = self.resolve(name)?;
+        let this = self.symbol(symbol)?;
+
+        let (DataType::Instance, SymbolData::Address(address)) =
+            (this.props.elem_props.data_type(), &this.data)
+        else {
+            return Err(VmError::NotAnInstance(name.to_string()));
+        };
+        let address = *address as u32;
+
+        // Instance -> prototype -> class
+        let mut class = this.parent;
+        while let Some(parent) = class {
+            let parent = self.symbol(parent)?;
+            if parent.props.elem_props.data_type() == DataType::Class {
+                break;
+            }
+            class = parent.parent;
+        }
+        let class = class.ok_or_else(|| VmError::NotAnInstance(name.to_string()))?;
+
+        let instance = self.memory.create(&self.dat.symbols, class);
+        self.write_instance(&Reference::global(symbol), Some(instance))?;
+
+        let current = self.current_instance.replace(instance);
+        let result = self.run(address);
+        self.current_instance = current;
+
+        result.map(|_| instance)
+    }
+
+    /// Index of the class symbol of an instance
+    pub fn instance_class(&self, instance: InstanceId) -> u32 {
+        self.memory.objects[instance.0 as usize].class
+    }
+
+    pub fn current_instance(&self) -> Option<InstanceId> {
+        self.current_instance
+    }
+
+    pub fn set_current_instance(&mut self, instance: Option<InstanceId>) {
+        self.current_instance = instance;
+    }
+
+    /// Reference to a global symbol
+    pub fn global(&self, name: &str) -> Result<Reference, VmError> {
+        Ok(Reference::global(self.resolve(name)?))
+    }
+
+    /// Reference to a field of an instance, `name` is the field name without the class prefix
+    pub fn field(&self, instance: InstanceId, name: &str) -> Result<Reference, VmError> {
+        let class = self.symbol_name(self.instance_class(instance));
+        let symbol = self.resolve(&format!("{class}.{name}"))?;
+        Ok(Reference::field(instance, symbol))
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    pub fn push_int(&mut self, value: i32) {
+        self.push(Value::Int(value));
+    }
+
+    pub fn push_float(&mut self, value: f32) {
+        self.push(Value::Float(value));
+    }
+
+    pub fn push_string(&mut self, value: impl Into<ZString>) {
+        self.push(Value::String(value.into()));
+    }
+
+    pub fn push_instance(&mut self, value: Option<InstanceId>) {
+        self.push(Value::Instance(value));
+    }
+
+    pub fn pop(&mut self) -> Result<Value, VmError> {
+        self.stack.pop().ok_or(VmError::StackUnderflow)
+    }
+
+    pub fn pop_int(&mut self) -> Result<i32, VmError> {
+        match self.pop()? {
+            Value::Int(v) => Ok(v),
+            Value::Reference(reference) => self.read_int(&reference),
+            found => Err(VmError::TypeMismatch {
+                expected: "int",
+                found,
+            }),
+        }
+    }
+
+    pub fn pop_float(&mut self) -> Result<f32, VmError> {
+        match self.pop()? {
+            Value::Float(v) => Ok(v),
+            // Float literals are pushed by `PushInt`
+            Value::Int(v) => Ok(f32::from_bits(v as u32)),
+            Value::Reference(reference) => self.read_float(&reference),
+            found => Err(VmError::TypeMismatch {
+                expected: "float",
+                found,
+            }),
+        }
+    }
+
+    pub fn pop_string(&mut self) -> Result<ZString, VmError> {
+        match self.pop()? {
+            Value::String(v) => Ok(v),
+            Value::Reference(reference) => self.read_string(&reference),
+            found => Err(VmError::TypeMismatch {
+                expected: "string",
+                found,
+            }),
+        }
+    }
+
+    pub fn pop_instance(&mut self) -> Result<Option<InstanceId>, VmError> {
+        match self.pop()? {
+            Value::Instance(v) => Ok(v),
+            Value::Reference(reference) => self.read_instance(&reference),
+            found => Err(VmError::TypeMismatch {
+                expected: "instance",
+                found,
+            }),
+        }
+    }
+
+    fn pop_reference(&mut self) -> Result<Reference, VmError> {
+        match self.pop()? {
+            Value::Reference(reference) => Ok(reference),
+            found => Err(VmError::TypeMismatch {
+                expected: "reference",
+                found,
+            }),
+        }
+    }
+
+    pub fn read_int(&self, reference: &Reference) -> Result<i32, VmError> {
+        match self.memory.get(reference)? {
+            Storage::Int(v) => v
+                .get(reference.index as usize)
+                .copied()
+                .ok_or(VmError::InvalidReference(*reference)),
+            _ => Err(VmError::WrongType {
+                reference: *reference,
+                expected: "int",
+            }),
+        }
+    }
+
+    pub fn write_int(&mut self, reference: &Reference, value: i32) -> Result<(), VmError> {
+        match self.memory.get_mut(reference)? {
+            Storage::Int(v) => {
+                *v.get_mut(reference.index as usize)
+                    .ok_or(VmError::InvalidReference(*reference))? = value;
+                Ok(())
+            }
+            _ => Err(VmError::WrongType {
+                reference: *reference,
+                expected: "int",
+            }),
+        }
+    }
+
+    pub fn read_float(&self, reference: &Reference) -> Result<f32, VmError> {
+        match self.memory.get(reference)? {
+            Storage::Float(v) => v
+                .get(reference.index as usize)
+                .copied()
+                .ok_or(VmError::InvalidReference(*reference)),
+            _ => Err(VmError::WrongType {
+                reference: *reference,
+                expected: "float",
+            }),
+        }
+    }
+
+    pub fn write_float(&mut self, reference: &Reference, value: f32) -> Result<(), VmError> {
+        match self.memory.get_mut(reference)? {
+            Storage::Float(v) => {
+                *v.get_mut(reference.index as usize)
+                    .ok_or(VmError::InvalidReference(*reference))? = value;
+                Ok(())
+            }
+            _ => Err(VmError::WrongType {
+                reference: *reference,
+                expected: "float",
+            }),
+        }
+    }
+
+    pub fn read_string(&self, reference: &Reference) -> Result<ZString, VmError> {
+        match self.memory.get(reference)? {
+            Storage::String(v) => v
+                .get(reference.index as usize)
+                .cloned()
+                .ok_or(VmError::InvalidReference(*reference)),
+            _ => Err(VmError::WrongType {
+                reference: *reference,
+                expected: "string",
+            }),
+        }
+    }
+
+    pub fn write_string(&mut self, reference: &Reference, value: ZString) -> Result<(), VmError> {
+        match self.memory.get_mut(reference)? {
+            Storage::String(v) => {
+                *v.get_mut(reference.index as usize)
+                    .ok_or(VmError::InvalidReference(*reference))? = value;
+                Ok(())
+            }
+            _ => Err(VmError::WrongType {
+                reference: *reference,
+                expected: "string",
+            }),
+        }
+    }
+
+    pub fn read_instance(&self, reference: &Reference) -> Result<Option<InstanceId>, VmError> {
+        match self.memory.get(reference)? {
+            Storage::Instance(v) => Ok(*v),
+            // `self` in a prototype body refers to the prototype, which stands for the instance
+            // that is being initialized
+            Storage::None
+                if self.symbol(reference.symbol)?.props.elem_props.data_type()
+                    == DataType::Prototype =>
+            {
+                Ok(self.current_instance)
+            }
+            _ => Err(VmError::WrongType {
+                reference: *reference,
+                expected: "instance",
+            }),
+        }
+    }
+
+    pub fn write_instance(
+        &mut self,
+        reference: &Reference,
+        value: Option<InstanceId>,
+    ) -> Result<(), VmError> {
+        match self.memory.get_mut(reference)? {
+            Storage::Instance(v) => {
+                *v = value;
+                Ok(())
+            }
+            _ => Err(VmError::WrongType {
+                reference: *reference,
+                expected: "instance",
+            }),
+        }
+    }
+
+    /// Reference pushed by `PushVar` and friends, fields belong to the current instance
+    fn reference(&self, symbol: u32, index: u8) -> Result<Reference, VmError> {
+        let flags = self.symbol(symbol)?.props.elem_props.flags();
+
+        let instance = if flags.contains(PropFlag::CLASS_VAR) {
+            let instance = self
+                .current_instance
+                .ok_or_else(|| VmError::NoInstance(self.symbol_name(symbol)))?;
+            Some(instance)
+        } else {
+            None
+        };
+
+        Ok(Reference {
+            symbol,
+            index,
+            instance,
+        })
+    }
+
+    fn call_extern(&mut self, symbol: u32) -> Result<(), VmError> {
+        let mut function = self
+            .externs
+            .remove(&symbol)
+            .ok_or_else(|| VmError::UnregisteredExtern(self.symbol_name(symbol)))?;
+
+        let result = function.call(self);
+        self.externs.insert(symbol, function);
+        result
+    }
+
+    /// Execute code starting at `address` until it returns
+    fn run(&mut self, address: u32) -> Result<(), VmError> {
+        let depth = self.frames.len();
+        if depth >= MAX_CALL_DEPTH {
+            return Err(VmError::CallDepthExceeded);
+        }
+
+        // Externs can call back into scripts, so the caller has to be resumed afterwards
+        let pc = self.pc;
+        self.frames.push(Frame {
+            return_address: None,
+        });
+        self.pc = address;
+
+        let result = loop {
+            if self.frames.len() == depth {
+                break Ok(());
+            }
+            if let Err(err) = self.step() {
+                break Err(err);
+            }
+        };
+
+        self.frames.truncate(depth);
+        self.pc = pc;
+        result
+    }
+
+    fn step(&mut self) -> Result<(), VmError> {
+        let address = self.pc;
+        let code = self
+            .dat
+            .bytecode
+            .as_bytes()
+            .get(address as usize..)
+            .unwrap_or_default();
+
+        let instruction = code
+            .first()
+            .and_then(|opcode| Opcode::from_u8(*opcode))
+            .and_then(|_| Instruction::decode(code).ok())
+            .ok_or(VmError::InvalidInstruction(address))?;
+        self.pc += instruction.size() as u32;
+
+        match (instruction.opcode, instruction.data) {
+            (Opcode::Add, _) => self.binary(|a, b| Ok(a.wrapping_add(b)))?,
+            (Opcode::Sub, _) => self.binary(|a, b| Ok(a.wrapping_sub(b)))?,
+            (Opcode::Mul, _) => self.binary(|a, b| Ok(a.wrapping_mul(b)))?,
+            (Opcode::Div, _) => self.binary(divide)?,
+            (Opcode::Mod, _) => self.binary(|a, b| match b {
+                0 => Err(VmError::DivisionByZero),
+                b => Ok(a.wrapping_rem(b)),
+            })?,
+            (Opcode::Or, _) => self.binary(|a, b| Ok(a | b))?,
+            (Opcode::AndB, _) => self.binary(|a, b| Ok(a & b))?,
+            (Opcode::Lt, _) => self.binary(|a, b| Ok((a < b) as i32))?,
+            (Opcode::Gt, _) => self.binary(|a, b| Ok((a > b) as i32))?,
+            (Opcode::Orr, _) => self.binary(|a, b| Ok((a != 0 || b != 0) as i32))?,
+            (Opcode::And, _) => self.binary(|a, b| Ok((a != 0 && b != 0) as i32))?,
+            (Opcode::Lsl, _) => self.binary(|a, b| Ok(a.wrapping_shl(b as u32)))?,
+            (Opcode::Lsr, _) => self.binary(|a, b| Ok(a.wrapping_shr(b as u32)))?,
+            (Opcode::Lte, _) => self.binary(|a, b| Ok((a <= b) as i32))?,
+            (Opcode::Eq, _) => self.binary(|a, b| Ok((a == b) as i32))?,
+            (Opcode::Neq, _) => self.binary(|a, b| Ok((a != b) as i32))?,
+            (Opcode::Gte, _) => self.binary(|a, b| Ok((a >= b) as i32))?,
+
+            (Opcode::AddMovI, _) => self.assign(|a, b| Ok(a.wrapping_add(b)))?,
+            (Opcode::SubMovI, _) => self.assign(|a, b| Ok(a.wrapping_sub(b)))?,
+            (Opcode::MulMovI, _) => self.assign(|a, b| Ok(a.wrapping_mul(b)))?,
+            (Opcode::DivMovI, _) => self.assign(divide)?,
+
+            (Opcode::Plus, _) => self.unary(|a| a)?,
+            (Opcode::Negate, _) => self.unary(i32::wrapping_neg)?,
+            (Opcode::Not, _) => self.unary(|a| (a == 0) as i32)?,
+            (Opcode::Cmpl, _) => self.unary(|a| !a)?,
+
+            (Opcode::MovInt | Opcode::MovVF, _) => {
+                let target = self.pop_reference()?;
+                let value = self.pop_int()?;
+                self.write_int(&target, value)?;
+            }
+            (Opcode::MovF, _) => {
+                let target = self.pop_reference()?;
+                let value = self.pop_float()?;
+                self.write_float(&target, value)?;
+            }
+            (Opcode::MovS | Opcode::MovSs, _) => {
+                let target = self.pop_reference()?;
+                let value = self.pop_string()?;
+                self.write_string(&target, value)?;
+            }
+            (Opcode::MovVI, _) => {
+                let target = self.pop_reference()?;
+                let value = self.pop_instance()?;
+                self.write_instance(&target, value)?;
+            }
+
+            (Opcode::Nop, _) => {}
+            (Opcode::Return, _) => {
+                let frame = self.frames.pop().ok_or(VmError::StackUnderflow)?;
+                if let Some(return_address) = frame.return_address {
+                    self.pc = return_address;
+                }
+            }
+            (Opcode::Call, InstructionData::Address(target)) => {
+                if self.frames.len() >= MAX_CALL_DEPTH {
+                    return Err(VmError::CallDepthExceeded);
+                }
+                self.frames.push(Frame {
+                    return_address: Some(self.pc),
+                });
+                self.pc = target;
+            }
+            (Opcode::CallExtern, InstructionData::Symbol(symbol)) => self.call_extern(symbol)?,
+            (Opcode::B, InstructionData::Address(target)) => self.pc = target,
+            (Opcode::Bz, InstructionData::Address(target)) => {
+                if self.pop_int()? == 0 {
+                    self.pc = target;
+                }
+            }
+
+            (Opcode::PushInt, InstructionData::Immediate(v)) => self.push_int(v),
+            (Opcode::PushVar | Opcode::PushVarInstance, InstructionData::Symbol(symbol)) => {
+                let reference = self.reference(symbol, 0)?;
+                self.push(Value::Reference(reference));
+            }
+            (Opcode::PushVV, InstructionData::SymbolIndex { symbol, index }) => {
+                let reference = self.reference(symbol, index)?;
+                self.push(Value::Reference(reference));
+            }
+            (Opcode::GMovI, InstructionData::Symbol(symbol)) => {
+                self.current_instance = self.read_instance(&Reference::global(symbol))?;
+            }
+
+            _ => return Err(VmError::InvalidInstruction(address)),
+        }
+
+        Ok(())
+    }
+
+    /// `a` is on top of the stack, it's the left operand
+    fn binary(&mut self, op: impl FnOnce(i32, i32) -> Result<i32, VmError>) -> Result<(), VmError> {
+        let a = self.pop_int()?;
+        let b = self.pop_int()?;
+        self.push_int(op(a, b)?);
+        Ok(())
+    }
+
+    /// Compound assignment, the target is on top of the stack
+    fn assign(&mut self, op: impl FnOnce(i32, i32) -> Result<i32, VmError>) -> Result<(), VmError> {
+        let target = self.pop_reference()?;
+        let b = self.pop_int()?;
+        let a = self.read_int(&target)?;
+        self.write_int(&target, op(a, b)?)
+    }
+
+    fn unary(&mut self, op: impl FnOnce(i32) -> i32) -> Result<(), VmError> {
+        let a = self.pop_int()?;
+        self.push_int(op(a));
+        Ok(())
+    }
+}
+
+fn divide(a: i32, b: i32) -> Result<i32, VmError> {
+    match b {
+        0 => Err(VmError::DivisionByZero),
+        b => Ok(a.wrapping_div(b)),
+    }
+}
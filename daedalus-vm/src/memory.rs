@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use dat_file::{
+    properties::{DataType, PropFlag},
+    Symbol, SymbolData,
+};
+use zstring::ZString;
+
+use crate::VmError;
+
+/// Handle of an object created by [`crate::Vm::init_instance`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstanceId(pub(crate) u32);
+
+/// Element of a global symbol, or of a field of an instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    pub symbol: u32,
+    pub index: u8,
+    /// Instance owning the field, `None` for globals
+    pub instance: Option<InstanceId>,
+}
+
+impl Reference {
+    pub fn global(symbol: u32) -> Self {
+        Self {
+            symbol,
+            index: 0,
+            instance: None,
+        }
+    }
+
+    pub fn field(instance: InstanceId, symbol: u32) -> Self {
+        Self {
+            symbol,
+            index: 0,
+            instance: Some(instance),
+        }
+    }
+
+    /// Same reference, but to the element at `index` of an array
+    pub fn at(self, index: u8) -> Self {
+        Self { index, ..self }
+    }
+}
+
+/// Entry of the VM stack
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Floats pushed by scripts end up here too, as ints with the same bit pattern
+    Int(i32),
+    Float(f32),
+    String(ZString),
+    Instance(Option<InstanceId>),
+    Reference(Reference),
+}
+
+/// Values stored in a symbol or in a field
+#[derive(Debug, Clone)]
+pub(crate) enum Storage {
+    Int(Vec<i32>),
+    Float(Vec<f32>),
+    String(Vec<ZString>),
+    Instance(Option<InstanceId>),
+    /// Classes, prototypes, functions, nothing that can be written to
+    None,
+}
+
+impl Storage {
+    pub fn new(symbol: &Symbol) -> Self {
+        let props = &symbol.props.elem_props;
+        let flags = props.flags();
+        // Parameters are emitted with no elements at all
+        let count = props.count().max(1) as usize;
+
+        match (props.data_type(), &symbol.data) {
+            (DataType::Int, SymbolData::Int(v)) => Storage::Int(resized(v, count)),
+            (DataType::Float, SymbolData::Float(v)) => Storage::Float(resized(v, count)),
+            (DataType::String, SymbolData::String(v)) => Storage::String(resized(v, count)),
+            (DataType::Int, _) => Storage::Int(vec![0; count]),
+            (DataType::Float, _) => Storage::Float(vec![0.0; count]),
+            (DataType::String, _) => Storage::String(vec![ZString::default(); count]),
+            // Function variables hold the symbol index of the function they point to
+            (DataType::Func, _) if !flags.contains(PropFlag::CONST) => Storage::Int(vec![0; count]),
+            (DataType::Instance, _) => Storage::Instance(None),
+            _ => Storage::None,
+        }
+    }
+}
+
+fn resized<T: Clone + Default>(values: &[T], count: usize) -> Vec<T> {
+    let mut values = values.to_vec();
+    values.resize(count, T::default());
+    values
+}
+
+#[derive(Debug)]
+pub(crate) struct Object {
+    pub class: u32,
+    pub fields: HashMap<u32, Storage>,
+}
+
+/// Storage of all symbols and objects
+#[derive(Debug)]
+pub(crate) struct Memory {
+    pub globals: Vec<Storage>,
+    pub objects: Vec<Object>,
+}
+
+impl Memory {
+    pub fn new(symbols: &[Symbol]) -> Self {
+        let globals = symbols
+            .iter()
+            .map(|symbol| {
+                if symbol
+                    .props
+                    .elem_props
+                    .flags()
+                    .contains(PropFlag::CLASS_VAR)
+                {
+                    Storage::None
+                } else {
+                    Storage::new(symbol)
+                }
+            })
+            .collect();
+
+        Self {
+            globals,
+            objects: Vec::new(),
+        }
+    }
+
+    /// New object with every field of `class` set to its default
+    pub fn create(&mut self, symbols: &[Symbol], class: u32) -> InstanceId {
+        let fields = symbols
+            .iter()
+            .enumerate()
+            .filter(|(_, symbol)| {
+                symbol.parent == Some(class)
+                    && symbol
+                        .props
+                        .elem_props
+                        .flags()
+                        .contains(PropFlag::CLASS_VAR)
+            })
+            .map(|(id, symbol)| (id as u32, Storage::new(symbol)))
+            .collect();
+
+        self.objects.push(Object { class, fields });
+        InstanceId(self.objects.len() as u32 - 1)
+    }
+
+    pub fn get(&self, reference: &Reference) -> Result<&Storage, VmError> {
+        match reference.instance {
+            None => self.globals.get(reference.symbol as usize),
+            Some(instance) => self.objects[instance.0 as usize]
+                .fields
+                .get(&reference.symbol),
+        }
+        .ok_or(VmError::InvalidReference(*reference))
+    }
+
+    pub fn get_mut(&mut self, reference: &Reference) -> Result<&mut Storage, VmError> {
+        match reference.instance {
+            None => self.globals.get_mut(reference.symbol as usize),
+            Some(instance) => self.objects[instance.0 as usize]
+                .fields
+                .get_mut(&reference.symbol),
+        }
+        .ok_or(VmError::InvalidReference(*reference))
+    }
+}
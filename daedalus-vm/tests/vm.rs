@@ -0,0 +1,204 @@
+use daedalus_compiler::test_support::compile;
+use daedalus_vm::{Value, Vm, VmError};
+use indoc::indoc;
+use zstring::ZString;
+
+fn vm(src: &str) -> Vm {
+    Vm::new(compile(src))
+}
+
+#[test]
+fn arithmetic_and_branches() {
+    let mut vm = vm(indoc! {"
+    func int calc(var int a, var int b) {
+        var int c;
+        c = a * b;
+        c += 10;
+        if c > 20 {
+            return c - 1;
+        } else if c == 20 {
+            return (c / 3) - 1 | 8;
+        };
+        return -c;
+    };
+    "});
+
+    let mut calc = |a, b| {
+        vm.push_int(a);
+        vm.push_int(b);
+        vm.call("calc").unwrap();
+        vm.pop_int().unwrap()
+    };
+
+    assert_eq!(calc(3, 5), 24);
+    assert_eq!(calc(2, 5), 13);
+    assert_eq!(calc(1, 1), -11);
+    assert!(vm.stack().is_empty());
+}
+
+#[test]
+fn globals_floats_and_strings() {
+    let mut vm = vm(indoc! {r#"
+    const int LIMIT = 7;
+    var string last;
+    var float speed;
+    var int calls[2];
+
+    func void update(var string name) {
+        last = name;
+        speed = 1.5;
+        calls[1] += 1;
+    };
+    "#});
+
+    vm.push_string(b"hero");
+    vm.call("update").unwrap();
+    vm.push_string(b"bandit");
+    vm.call("update").unwrap();
+
+    let last = vm.global("last").unwrap();
+    assert_eq!(vm.read_string(&last).unwrap(), ZString::from(b"bandit"));
+    let speed = vm.global("speed").unwrap();
+    assert_eq!(vm.read_float(&speed).unwrap(), 1.5);
+    let calls = vm.global("calls").unwrap();
+    assert_eq!(vm.read_int(&calls.at(1)).unwrap(), 2);
+    assert!(vm.read_int(&calls.at(2)).is_err());
+
+    let limit = vm.global("limit").unwrap();
+    assert_eq!(vm.read_int(&limit).unwrap(), 7);
+}
+
+#[test]
+fn externs() {
+    let mut vm = vm(indoc! {r#"
+    extern func void Host_Log(var string msg, var int level)
+    extern func int Host_Count()
+
+    func int run() {
+        Host_Log("started", 2);
+        return Host_Count() + Host_Count();
+    };
+    "#});
+
+    let log = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let sink = log.clone();
+    vm.register_extern("Host_Log", move |vm: &mut Vm| {
+        let level = vm.pop_int()?;
+        let msg = vm.pop_string()?;
+        sink.borrow_mut().push((msg.to_string(), level));
+        Ok(())
+    })
+    .unwrap();
+
+    let mut count = 0;
+    vm.register_extern("Host_Count", move |vm: &mut Vm| {
+        count += 1;
+        vm.push_int(count);
+        Ok(())
+    })
+    .unwrap();
+
+    vm.call("run").unwrap();
+    assert_eq!(vm.pop_int().unwrap(), 3);
+    assert_eq!(*log.borrow(), [("started".to_string(), 2)]);
+
+    assert!(matches!(
+        vm.register_extern("run", |_: &mut Vm| Ok(())),
+        Err(VmError::NotAFunction(_))
+    ));
+}
+
+#[test]
+fn unregistered_extern() {
+    let mut vm = vm(indoc! {"
+    extern func int Host_Count()
+    func int run() { return Host_Count(); };
+    "});
+
+    assert!(matches!(
+        vm.call("run"),
+        Err(VmError::UnregisteredExtern(name)) if name == "HOST_COUNT"
+    ));
+}
+
+#[test]
+fn instances() {
+    let mut vm = vm(indoc! {r#"
+    class C_Test {
+        var int attribute[2];
+        var string name;
+    };
+
+    prototype Test_Default(C_Test) {
+        attribute[1] = 10;
+        name = "default";
+    };
+
+    instance hero(Test_Default) {
+        name = "Hero";
+        self.attribute[0] = 5;
+    };
+
+    instance other(C_Test) {};
+
+    func void heal() {
+        hero.attribute[0] = hero.attribute[1];
+        other.name = hero.name;
+    };
+    "#});
+
+    let hero = vm.init_instance("hero").unwrap();
+    let name = vm.field(hero, "name").unwrap();
+    let attribute = vm.field(hero, "attribute").unwrap();
+
+    assert_eq!(vm.read_string(&name).unwrap(), ZString::from(b"Hero"));
+    assert_eq!(vm.read_int(&attribute).unwrap(), 5);
+    assert_eq!(vm.read_int(&attribute.at(1)).unwrap(), 10);
+
+    let other = vm.init_instance("other").unwrap();
+    vm.call("heal").unwrap();
+
+    assert_eq!(vm.read_int(&attribute).unwrap(), 10);
+    let other_name = vm.field(other, "name").unwrap();
+    assert_eq!(vm.read_string(&other_name).unwrap(), ZString::from(b"Hero"));
+
+    let symbol = vm.global("hero").unwrap();
+    assert_eq!(vm.read_instance(&symbol).unwrap(), Some(hero));
+    assert_eq!(vm.instance_class(hero), vm.symbol_id("C_Test").unwrap());
+}
+
+#[test]
+fn typed_stack() {
+    let mut vm = vm("func int f() { return 1; };");
+
+    vm.push_string(b"text");
+    assert!(matches!(
+        vm.pop_int(),
+        Err(VmError::TypeMismatch {
+            expected: "int",
+            found: Value::String(_)
+        })
+    ));
+    assert!(matches!(vm.pop(), Err(VmError::StackUnderflow)));
+}
+
+#[test]
+fn string_assignments() {
+    let mut dat = compile(indoc! {"
+    var string last;
+    func void update(var string name) { last = name; };
+    "});
+    // The compiler never emits `MovSs`, so it is patched into the code
+    let listing = dat_file::disasm::Disassembly::new(&dat)
+        .to_string()
+        .replace("MovS\n", "MovSs\n");
+    assert!(listing.contains("MovSs"));
+    dat.assemble(&listing).unwrap();
+
+    let mut vm = Vm::new(dat);
+    vm.push_string(b"hero");
+    vm.call("update").unwrap();
+
+    let last = vm.global("last").unwrap();
+    assert_eq!(vm.read_string(&last).unwrap(), ZString::from(b"hero"));
+}
@@ -0,0 +1,23 @@
+use std::io::Cursor;
+
+/// Replace code of a DAT file with an edited `dat_disasm` listing
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let dat = args.next().expect("Arg `dat` not found");
+    let asm = args.next().expect("Arg `asm` not found");
+    let out = args.next().expect("Arg `out` not found");
+
+    let data = std::fs::read(dat).unwrap();
+    let mut dat = dat_file::DatFile::decode(&mut Cursor::new(data)).unwrap();
+
+    let src = std::fs::read_to_string(asm).unwrap();
+    if let Err(err) = dat.assemble(&src) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+
+    let mut encoded = Vec::new();
+    dat.encode(&mut encoded).unwrap();
+    std::fs::write(out, encoded).unwrap();
+}
@@ -1,6 +1,7 @@
-use pretty_assertions::assert_eq;
 use std::io::Cursor;
 
+use dat_file::diff::DatDiff;
+
 fn main() {
     let mut args = std::env::args().skip(1);
 
@@ -15,23 +16,10 @@ fn main() {
     let dat_b = dat_file::DatFile::decode(&mut Cursor::new(data)).unwrap();
     // dat_file::debug_print(&dat_b);
 
-    assert_eq!(dat_a.sort_indexes, dat_b.sort_indexes);
-    assert_eq!(dat_a.symbols.len(), dat_b.symbols.len());
-
-    for (a, b) in dat_a.symbols.iter().zip(dat_b.symbols.iter()) {
-        assert_eq!(a, b);
-    }
-
-    assert_eq!(
-        dat_a.bytecode.as_bytes().len(),
-        dat_b.bytecode.as_bytes().len()
-    );
+    let diff = DatDiff::new(&dat_a, &dat_b);
+    print!("{diff}");
 
-    for (a, b) in dat_a
-        .bytecode
-        .instructions()
-        .zip(dat_b.bytecode.instructions())
-    {
-        assert_eq!(a, b);
+    if !diff.is_identical() {
+        std::process::exit(1);
     }
 }
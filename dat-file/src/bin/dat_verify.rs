@@ -0,0 +1,24 @@
+use std::io::Cursor;
+
+/// Check the code of a DAT file for stack and control flow errors
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let dat = args.next().expect("Arg `dat` not found");
+
+    let data = std::fs::read(dat).unwrap();
+    let dat = dat_file::DatFile::decode(&mut Cursor::new(data)).unwrap();
+
+    let errors = dat.verify();
+    for (symbol, err) in errors.iter() {
+        let name = dat.symbols[*symbol as usize]
+            .name
+            .as_ref()
+            .map_or_else(|| format!("#{symbol}"), |name| name.to_string());
+        eprintln!("{name}: {err}");
+    }
+
+    if !errors.is_empty() {
+        std::process::exit(1);
+    }
+}
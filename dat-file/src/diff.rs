@@ -0,0 +1,311 @@
+//! Symbol by symbol and instruction by instruction comparison of two DAT files, used to measure
+//! how far compiled output is from the one produced by the original compiler
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use daedalus_bytecode::Instruction;
+
+use crate::{
+    disasm::Disassembly,
+    properties::{DataType, PropFlag},
+    DatFile, Symbol, SymbolData,
+};
+
+/// How many mismatching symbols get listed by name in the report
+const LISTED_MISMATCHES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SymbolKind {
+    Class,
+    ClassVar,
+    Prototype,
+    Instance,
+    Func,
+    ExternFunc,
+    Const,
+    Var,
+    /// Autogenerated symbols of string literals
+    StringLiteral,
+}
+
+impl SymbolKind {
+    pub fn of(symbol: &Symbol) -> Self {
+        let props = &symbol.props.elem_props;
+        let flags = props.flags();
+        let is_const = flags.contains(PropFlag::CONST);
+
+        let is_generated = symbol
+            .name
+            .as_ref()
+            .is_some_and(|name| name.first() == Some(&0xFF));
+
+        if flags.contains(PropFlag::CLASS_VAR) {
+            return SymbolKind::ClassVar;
+        }
+
+        match props.data_type() {
+            DataType::String if is_const && is_generated => SymbolKind::StringLiteral,
+            DataType::Class => SymbolKind::Class,
+            DataType::Prototype => SymbolKind::Prototype,
+            DataType::Instance if is_const => SymbolKind::Instance,
+            DataType::Func if flags.contains(PropFlag::EXTERNAL) => SymbolKind::ExternFunc,
+            DataType::Func if is_const => SymbolKind::Func,
+            _ if is_const => SymbolKind::Const,
+            _ => SymbolKind::Var,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Count {
+    pub total: usize,
+    pub mismatched: usize,
+}
+
+impl Count {
+    fn add(&mut self, matches: bool) {
+        self.total += 1;
+        if !matches {
+            self.mismatched += 1;
+        }
+    }
+
+    /// Position by position, instructions only one of the blocks has are mismatches
+    fn add_block(&mut self, found: &[Instruction], expected: &[Instruction]) {
+        for i in 0..found.len().max(expected.len()) {
+            self.add(found.get(i) == expected.get(i));
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolMismatch {
+    pub index: usize,
+    pub kind: SymbolKind,
+    pub name: String,
+}
+
+/// Differences between a DAT file and the reference one
+#[derive(Debug, Clone)]
+pub struct DatDiff {
+    /// Symbol count of the compared file and of the reference one
+    pub symbol_count: (usize, usize),
+    pub sort_indexes_match: bool,
+    /// Symbols are paired by name, kind is taken from the reference symbol. Symbols only one of
+    /// the files has are mismatches of their own kind. Parents are compared by name, and code
+    /// addresses are counted as drift instead of being compared.
+    pub symbols: BTreeMap<SymbolKind, Count>,
+    pub symbol_mismatches: Vec<SymbolMismatch>,
+    /// Paired symbols at a different index than in the reference
+    pub index_drift: usize,
+    /// Paired functions, instances and prototypes whose code starts at a different address
+    pub address_drift: usize,
+    /// Bytecode size of the compared file and of the reference one
+    pub bytecode_len: (usize, usize),
+    /// Code blocks are paired by the name of their symbol, so code that moved is still compared
+    /// with its counterpart. Blocks only one of the files has are mismatches.
+    pub instructions: Count,
+}
+
+impl DatDiff {
+    pub fn new(dat: &DatFile, reference: &DatFile) -> Self {
+        let mut symbols = BTreeMap::<SymbolKind, Count>::new();
+        let mut symbol_mismatches = Vec::new();
+        let mut index_drift = 0;
+        let mut address_drift = 0;
+
+        let mut mismatch = |index: usize, symbol: &Symbol, matches: bool| {
+            let kind = SymbolKind::of(symbol);
+            symbols.entry(kind).or_default().add(matches);
+            if !matches {
+                symbol_mismatches.push(SymbolMismatch {
+                    index,
+                    kind,
+                    name: symbol_name(symbol),
+                });
+            }
+        };
+
+        let mut compiled: HashMap<String, usize> = dat
+            .symbols
+            .iter()
+            .enumerate()
+            .map(|(index, symbol)| (symbol_name(symbol), index))
+            .collect();
+
+        for (index, expected) in reference.symbols.iter().enumerate() {
+            let Some(found_index) = compiled.remove(&symbol_name(expected)) else {
+                mismatch(index, expected, false);
+                continue;
+            };
+            let found = &dat.symbols[found_index];
+
+            if found_index != index {
+                index_drift += 1;
+            }
+            if let (SymbolData::Address(a), SymbolData::Address(b)) = (&found.data, &expected.data)
+            {
+                address_drift += (a != b) as usize;
+            }
+
+            mismatch(
+                index,
+                expected,
+                same_symbol(dat, found, reference, expected),
+            );
+        }
+
+        // Only in the compiled file
+        let mut extra: Vec<usize> = compiled.into_values().collect();
+        extra.sort();
+        for index in extra {
+            mismatch(index, &dat.symbols[index], false);
+        }
+
+        let mut instructions = Count::default();
+        let mut compiled = code_blocks(dat);
+        for (name, expected) in code_blocks(reference) {
+            let found = compiled.remove(&name).unwrap_or_default();
+            instructions.add_block(&found, &expected);
+        }
+        for found in compiled.into_values() {
+            instructions.add_block(&found, &[]);
+        }
+
+        Self {
+            symbol_count: (dat.symbols.len(), reference.symbols.len()),
+            sort_indexes_match: dat.sort_indexes == reference.sort_indexes,
+            symbols,
+            symbol_mismatches,
+            index_drift,
+            address_drift,
+            bytecode_len: (
+                dat.bytecode.as_bytes().len(),
+                reference.bytecode.as_bytes().len(),
+            ),
+            instructions,
+        }
+    }
+
+    /// Whether both files decode to the same content
+    pub fn is_identical(&self) -> bool {
+        self.symbol_count.0 == self.symbol_count.1
+            && self.sort_indexes_match
+            && self.symbol_mismatches.is_empty()
+            && self.index_drift == 0
+            && self.address_drift == 0
+            && self.bytecode_len.0 == self.bytecode_len.1
+            && self.instructions.mismatched == 0
+    }
+}
+
+fn symbol_name(symbol: &Symbol) -> String {
+    symbol
+        .name
+        .as_ref()
+        .map(|name| name.to_string())
+        .unwrap_or_default()
+}
+
+/// Whether two symbols are the same apart from where they and their code ended up, parents are
+/// compared by name
+fn same_symbol(dat: &DatFile, found: &Symbol, reference: &DatFile, expected: &Symbol) -> bool {
+    let same_data = match (&found.data, &expected.data) {
+        (SymbolData::Address(_), SymbolData::Address(_)) => true,
+        (found, expected) => found == expected,
+    };
+
+    let parent_name = |dat: &DatFile, parent: Option<u32>| {
+        parent.map(|parent| dat.symbols.get(parent as usize).map(symbol_name))
+    };
+
+    found.props == expected.props
+        && found.code_span == expected.code_span
+        && same_data
+        && parent_name(dat, found.parent) == parent_name(reference, expected.parent)
+}
+
+/// Instructions of every function, instance and prototype, by the name of the symbol. Code that
+/// does not decode is compared as if it was empty.
+fn code_blocks(dat: &DatFile) -> HashMap<String, Vec<Instruction>> {
+    Disassembly::new(dat)
+        .blocks
+        .iter()
+        .map(|block| {
+            let name = dat.symbols[block.symbol as usize]
+                .name
+                .as_ref()
+                .map(|name| name.to_string())
+                .unwrap_or_default();
+            let instructions = dat
+                .bytecode
+                .decode_range(block.start..block.end)
+                .map(|code| {
+                    code.into_iter()
+                        .map(|(_, instruction)| instruction)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            (name, instructions)
+        })
+        .collect()
+}
+
+impl fmt::Display for DatDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "symbols: {} (reference {})",
+            self.symbol_count.0, self.symbol_count.1
+        )?;
+        writeln!(f, "sort indexes match: {}", self.sort_indexes_match)?;
+        writeln!(
+            f,
+            "drift: {} symbol(s) at another index, {} with code at another address",
+            self.index_drift, self.address_drift
+        )?;
+
+        writeln!(f, "{:<16}{:>10}{:>12}", "kind", "total", "mismatched")?;
+        for (kind, count) in self.symbols.iter() {
+            writeln!(
+                f,
+                "{:<16}{:>10}{:>12}",
+                format!("{kind:?}"),
+                count.total,
+                count.mismatched
+            )?;
+        }
+
+        writeln!(
+            f,
+            "bytecode: {} bytes (reference {})",
+            self.bytecode_len.0, self.bytecode_len.1
+        )?;
+        writeln!(
+            f,
+            "instructions: {} mismatched of {}",
+            self.instructions.mismatched, self.instructions.total
+        )?;
+
+        for mismatch in self.symbol_mismatches.iter().take(LISTED_MISMATCHES) {
+            writeln!(
+                f,
+                "mismatch: #{} {:?} {}",
+                mismatch.index, mismatch.kind, mismatch.name
+            )?;
+        }
+        if self.symbol_mismatches.len() > LISTED_MISMATCHES {
+            writeln!(
+                f,
+                "... and {} more",
+                self.symbol_mismatches.len() - LISTED_MISMATCHES
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+//! Readable listing of the bytecode, split into the functions, instances and prototypes owning
+//! it, the listing can be assembled back with [`DatFile::assemble`]
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use daedalus_bytecode::{
+    asm::{self, AsmError, SymbolResolver},
+    Instruction, InstructionData, Opcode,
+};
+
+use crate::{properties::DataType, DatFile, SymbolData};
+
+/// Code of a single function, instance or prototype
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub symbol: u32,
+    /// Address of the first instruction
+    pub start: u32,
+    /// Address right after the last instruction
+    pub end: u32,
+}
+
+#[derive(Debug)]
+pub struct Disassembly<'a> {
+    dat: &'a DatFile,
+    /// Sorted by address
+    pub blocks: Vec<CodeBlock>,
+    /// Branch targets and their label numbers, assigned in the order of addresses
+    pub labels: BTreeMap<u32, usize>,
+}
+
+impl<'a> Disassembly<'a> {
+    pub fn new(dat: &'a DatFile) -> Self {
+        let mut starts: Vec<(u32, u32)> = dat
+            .symbols
+            .iter()
+            .enumerate()
+            .filter_map(|(id, symbol)| match symbol.data {
+                SymbolData::Address(address) if symbol.has_code() => {
+                    Some((address as u32, id as u32))
+                }
+                _ => None,
+            })
+            .collect();
+        starts.sort();
+        starts.dedup_by_key(|(address, _)| *address);
+
+        let code_end = dat.bytecode.as_bytes().len() as u32;
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &(start, symbol))| CodeBlock {
+                symbol,
+                start,
+                end: starts.get(i + 1).map_or(code_end, |(next, _)| *next),
+            })
+            .collect();
+
+        let mut targets: Vec<u32> = dat
+            .bytecode
+            .instructions()
+            .filter_map(|instruction| match (instruction.opcode, instruction.data) {
+                (Opcode::B | Opcode::Bz, InstructionData::Address(target)) => Some(target),
+                _ => None,
+            })
+            .collect();
+        targets.sort();
+        targets.dedup();
+
+        let labels = targets
+            .into_iter()
+            .enumerate()
+            .map(|(label, address)| (address, label))
+            .collect();
+
+        Self {
+            dat,
+            blocks,
+            labels,
+        }
+    }
+
+    fn symbol_name(&self, symbol: u32) -> String {
+        match self
+            .dat
+            .symbols
+            .get(symbol as usize)
+            .and_then(|symbol| symbol.name.as_ref())
+        {
+            Some(name) => name.to_string(),
+            None => format!("#{symbol}"),
+        }
+    }
+
+    /// Name of the function starting at `address`, or its label
+    fn address_name(&self, address: u32) -> String {
+        if let Ok(block) = self
+            .blocks
+            .binary_search_by_key(&address, |block| block.start)
+        {
+            return self.symbol_name(self.blocks[block].symbol);
+        }
+
+        match self.labels.get(&address) {
+            Some(label) => format!("label_{label}"),
+            None => format!("0x{address:08x}"),
+        }
+    }
+
+    fn operand(&self, instruction: &Instruction) -> String {
+        match instruction.data {
+            InstructionData::None => String::new(),
+            InstructionData::Immediate(v) => v.to_string(),
+            InstructionData::Address(address) => self.address_name(address),
+            InstructionData::Symbol(symbol) => {
+                let mut name = self.symbol_name(symbol);
+                // Generated symbols of string literals say little on their own
+                if let Some(value) = self.literal(symbol) {
+                    name.push_str(&format!(" ; \"{value}\""));
+                }
+                name
+            }
+            InstructionData::SymbolIndex { symbol, index } => {
+                format!("{}[{index}]", self.symbol_name(symbol))
+            }
+        }
+    }
+
+    fn literal(&self, symbol: u32) -> Option<String> {
+        let symbol = self.dat.symbols.get(symbol as usize)?;
+        if symbol.name.as_ref()?.first() != Some(&0xFF) {
+            return None;
+        }
+
+        match &symbol.data {
+            SymbolData::String(v) => v.first().map(|v| v.to_string()),
+            _ => None,
+        }
+    }
+
+    fn block_header(&self, block: &CodeBlock) -> String {
+        let kind = match self.dat.symbols[block.symbol as usize]
+            .props
+            .elem_props
+            .data_type()
+        {
+            DataType::Instance => "instance",
+            DataType::Prototype => "prototype",
+            _ => "func",
+        };
+        format!("{kind} {}", self.symbol_name(block.symbol))
+    }
+}
+
+impl fmt::Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut blocks = self.blocks.iter().peekable();
+        let mut address = 0;
+
+        for instruction in self.dat.bytecode.instructions() {
+            if let Some(block) = blocks.next_if(|block| block.start <= address) {
+                if block.start != self.blocks[0].start {
+                    writeln!(f)?;
+                }
+                writeln!(f, "{}:", self.block_header(block))?;
+            }
+
+            if let Some(label) = self.labels.get(&address) {
+                writeln!(f, "label_{label}:")?;
+            }
+
+            let opcode = format!("{:?}", instruction.opcode);
+            let line = format!(
+                "  {address:08x}  {opcode:<16}{}",
+                self.operand(&instruction)
+            );
+            writeln!(f, "{}", line.trim_end())?;
+
+            address += instruction.size() as u32;
+        }
+
+        if address < self.dat.bytecode.as_bytes().len() as u32 {
+            writeln!(
+                f,
+                "  {address:08x}  ; invalid instruction, the rest is not decoded"
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Names of symbols as printed by [`Disassembly`], for resolving assembly against a DAT file
+#[derive(Debug)]
+pub struct SymbolNames<'a> {
+    dat: &'a DatFile,
+    names: HashMap<String, u32>,
+}
+
+impl<'a> SymbolNames<'a> {
+    pub fn new(dat: &'a DatFile) -> Self {
+        let names = dat
+            .symbols
+            .iter()
+            .enumerate()
+            .filter_map(|(id, symbol)| Some((symbol.name.as_ref()?.to_string(), id as u32)))
+            .collect();
+
+        Self { dat, names }
+    }
+}
+
+impl SymbolResolver for SymbolNames<'_> {
+    fn symbol_id(&self, name: &str) -> Option<u32> {
+        self.names.get(name).copied()
+    }
+
+    fn symbol_count(&self) -> u32 {
+        self.dat.symbols.len() as u32
+    }
+
+    fn has_code(&self, symbol: u32) -> bool {
+        self.dat
+            .symbols
+            .get(symbol as usize)
+            .is_some_and(|symbol| symbol.has_code())
+    }
+
+    fn symbol_address(&self, symbol: u32) -> Option<u32> {
+        match self.dat.symbols.get(symbol as usize)?.data {
+            SymbolData::Address(address) => Some(address as u32),
+            _ => None,
+        }
+    }
+}
+
+impl DatFile {
+    /// Replace the bytecode with assembled `src`, symbols with a header in it get their address
+    /// updated
+    pub fn assemble(&mut self, src: &str) -> Result<(), AsmError> {
+        let assembly = asm::assemble(src, &SymbolNames::new(self))?;
+
+        for (symbol, address) in assembly.blocks {
+            self.symbols[symbol as usize].data = SymbolData::Address(address as i32);
+        }
+        self.bytecode = assembly.bytecode;
+
+        Ok(())
+    }
+}
@@ -8,6 +8,10 @@ use zstring::ZString;
 
 use crate::properties::{DataType, PropFlag};
 
+pub mod diff;
+pub mod disasm;
+pub mod verify;
+
 #[derive(Debug, PartialEq)]
 pub struct Symbol {
     pub name: Option<ZString>,
@@ -150,6 +154,26 @@ impl Symbol {
 
         Ok(())
     }
+
+    /// Whether this is a function, instance or prototype with code in the bytecode
+    pub fn has_code(&self) -> bool {
+        let props = &self.props.elem_props;
+        matches!(
+            props.data_type(),
+            DataType::Func | DataType::Instance | DataType::Prototype
+        ) && props.flags().contains(PropFlag::CONST)
+            && !props.flags().contains(PropFlag::EXTERNAL)
+            && matches!(self.data, SymbolData::Address(_))
+    }
+
+    /// Type returned by a function, `Void` for functions without a return value and for symbols
+    /// that are not functions
+    pub fn return_type(&self) -> DataType {
+        if !self.props.elem_props.flags().contains(PropFlag::RETURN) {
+            return DataType::Void;
+        }
+        DataType::from_i32(self.props.off_cls_ret).unwrap_or(DataType::Void)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -171,6 +195,19 @@ pub struct DatFile {
 }
 
 impl DatFile {
+    /// Symbols of the parameters of `function`, they directly follow the symbol of the function,
+    /// `None` if the function or any of them is missing
+    pub fn params(&self, function: u32) -> Option<std::ops::Range<u32>> {
+        let count = self
+            .symbols
+            .get(function as usize)?
+            .props
+            .elem_props
+            .count();
+        let params = function + 1..function + 1 + count;
+        (params.end as usize <= self.symbols.len()).then_some(params)
+    }
+
     pub fn decode(mut r: impl Read) -> std::io::Result<Self> {
         let version = r.read_u8()?;
         let count = r.read_u32::<LittleEndian>()?;
@@ -261,6 +298,22 @@ pub mod properties {
         }
     }
 
+    impl std::fmt::Display for DataType {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let s = match self {
+                DataType::Void => "void",
+                DataType::Float => "float",
+                DataType::Int => "int",
+                DataType::String => "string",
+                DataType::Class => "class",
+                DataType::Func => "func",
+                DataType::Prototype => "prototype",
+                DataType::Instance => "instance",
+            };
+            f.write_str(s)
+        }
+    }
+
     #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
     pub struct SymbolCodeSpan {
         pub file_index: u19,
@@ -595,9 +648,7 @@ pub fn debug_print(dat: &DatFile) {
         }
     }
 
-    for i in dat.bytecode.instructions() {
-        println!("{i:?}");
-    }
+    print!("{}", disasm::Disassembly::new(dat));
 
     println!();
 }
@@ -0,0 +1,381 @@
+//! Static checks of the code of every function, instance and prototype, so broken DAT files get
+//! caught before the engine runs them
+//!
+//! The stack of every basic block is tracked as a list of value types, which has to be the same
+//! on every path into the block.
+
+use std::{collections::HashMap, ops::Range};
+
+use daedalus_bytecode::{
+    cfg::{Cfg, CfgError},
+    Instruction, InstructionData, Opcode,
+};
+
+use crate::{disasm::Disassembly, properties::DataType, DatFile, Symbol};
+
+/// What is known about a value on the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackType {
+    /// Immediate, or result of an operator
+    Int,
+    Float,
+    String,
+    Instance,
+    /// Variable pushed by `PushVar` and friends, of given type
+    Reference(DataType),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VerifyError {
+    #[error(transparent)]
+    Cfg(#[from] CfgError),
+    #[error("stack underflow at 0x{0:08x}")]
+    StackUnderflow(u32),
+    #[error("stack holds {found} values at 0x{address:08x}, but {expected} on another path")]
+    StackHeightMismatch {
+        address: u32,
+        expected: usize,
+        found: usize,
+    },
+    #[error("stack holds {found:?} at 0x{address:08x}, but {expected:?} on another path")]
+    StackTypeMismatch {
+        address: u32,
+        expected: StackType,
+        found: StackType,
+    },
+    #[error("`{opcode:?}` at 0x{address:08x} expects {expected}, found {found:?}")]
+    TypeMismatch {
+        address: u32,
+        opcode: Opcode,
+        expected: &'static str,
+        found: StackType,
+    },
+    #[error("return at 0x{address:08x} is missing a value of type {expected}")]
+    MissingReturnValue { address: u32, expected: DataType },
+    #[error("unknown symbol {symbol} at 0x{address:08x}")]
+    UnknownSymbol { address: u32, symbol: u32 },
+    #[error("call at 0x{0:08x} does not target a function")]
+    InvalidCall(u32),
+}
+
+impl DatFile {
+    /// Problems in the code, along with the symbol owning it, only the first one of each symbol is
+    /// reported
+    pub fn verify(&self) -> Vec<(u32, VerifyError)> {
+        let blocks = Disassembly::new(self).blocks;
+        let verifier = Verifier {
+            dat: self,
+            functions: blocks
+                .iter()
+                .map(|block| (block.start, block.symbol))
+                .collect(),
+        };
+
+        blocks
+            .iter()
+            .filter_map(|block| {
+                verifier
+                    .verify(block.symbol, block.start..block.end)
+                    .err()
+                    .map(|err| (block.symbol, err))
+            })
+            .collect()
+    }
+}
+
+struct Verifier<'a> {
+    dat: &'a DatFile,
+    /// Symbols of the code starting at an address
+    functions: HashMap<u32, u32>,
+}
+
+impl Verifier<'_> {
+    fn verify(&self, symbol: u32, range: Range<u32>) -> Result<(), VerifyError> {
+        let cfg = Cfg::new(&self.dat.bytecode, range.clone())?;
+        let owner = &self.dat.symbols[symbol as usize];
+
+        let ret = match owner.props.elem_props.data_type() {
+            DataType::Func => owner.return_type(),
+            _ => DataType::Void,
+        };
+
+        // Arguments are on the stack on entry, the function pops them itself
+        let entry = match owner.props.elem_props.data_type() {
+            DataType::Func => self
+                .params(range.start, symbol)?
+                .into_iter()
+                .map(|(_, ty)| value_type(ty))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut stacks: Vec<Option<Vec<StackType>>> = vec![None; cfg.blocks.len()];
+        stacks[0] = Some(entry);
+        let mut queue = vec![0];
+
+        while let Some(id) = queue.pop() {
+            let block = &cfg.blocks[id];
+            let mut stack = stacks[id].clone().expect("queued blocks have a stack");
+
+            for (address, instruction) in block.instructions.iter() {
+                self.step(*address, instruction, ret, &mut stack)?;
+            }
+
+            for &next in block.successors.iter() {
+                match &stacks[next] {
+                    None => {
+                        stacks[next] = Some(stack.clone());
+                        queue.push(next);
+                    }
+                    Some(expected) if expected.len() != stack.len() => {
+                        return Err(VerifyError::StackHeightMismatch {
+                            address: cfg.blocks[next].start,
+                            expected: expected.len(),
+                            found: stack.len(),
+                        });
+                    }
+                    Some(expected) => {
+                        let mismatch = expected.iter().zip(stack.iter()).find(|(a, b)| a != b);
+                        if let Some((expected, found)) = mismatch {
+                            return Err(VerifyError::StackTypeMismatch {
+                                address: cfg.blocks[next].start,
+                                expected: *expected,
+                                found: *found,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn step(
+        &self,
+        address: u32,
+        instruction: &Instruction,
+        ret: DataType,
+        stack: &mut Vec<StackType>,
+    ) -> Result<(), VerifyError> {
+        let opcode = instruction.opcode;
+        match (opcode, &instruction.data) {
+            (
+                Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Mod
+                | Opcode::Or
+                | Opcode::AndB
+                | Opcode::Lt
+                | Opcode::Gt
+                | Opcode::Orr
+                | Opcode::And
+                | Opcode::Lsl
+                | Opcode::Lsr
+                | Opcode::Lte
+                | Opcode::Eq
+                | Opcode::Neq
+                | Opcode::Gte,
+                _,
+            ) => {
+                pop(stack, address, opcode, DataType::Int)?;
+                pop(stack, address, opcode, DataType::Int)?;
+                stack.push(StackType::Int);
+            }
+            (Opcode::Plus | Opcode::Negate | Opcode::Not | Opcode::Cmpl, _) => {
+                pop(stack, address, opcode, DataType::Int)?;
+                stack.push(StackType::Int);
+            }
+            // Target is on top, the value right under it
+            (
+                Opcode::MovInt
+                | Opcode::MovF
+                | Opcode::MovS
+                | Opcode::MovSs
+                | Opcode::MovVF
+                | Opcode::MovVI
+                | Opcode::AddMovI
+                | Opcode::SubMovI
+                | Opcode::MulMovI
+                | Opcode::DivMovI,
+                _,
+            ) => {
+                let ty = match opcode {
+                    Opcode::MovF => DataType::Float,
+                    Opcode::MovS | Opcode::MovSs => DataType::String,
+                    Opcode::MovVF => DataType::Func,
+                    Opcode::MovVI => DataType::Instance,
+                    _ => DataType::Int,
+                };
+
+                let target = stack.pop().ok_or(VerifyError::StackUnderflow(address))?;
+                if !matches!(target, StackType::Reference(_)) || !assignable(ty, target) {
+                    return Err(VerifyError::TypeMismatch {
+                        address,
+                        opcode,
+                        expected: "a variable",
+                        found: target,
+                    });
+                }
+                pop(stack, address, opcode, ty)?;
+            }
+            // Values left by expression statements are fine, only the one returned is checked
+            (Opcode::Return, _) if ret != DataType::Void => {
+                if stack.is_empty() {
+                    return Err(VerifyError::MissingReturnValue {
+                        address,
+                        expected: ret,
+                    });
+                }
+                pop(stack, address, opcode, ret)?;
+            }
+            (Opcode::Call, InstructionData::Address(target)) => {
+                let function = self
+                    .functions
+                    .get(target)
+                    .copied()
+                    // Instances call their prototype first
+                    .filter(|symbol| {
+                        matches!(
+                            self.data_type(*symbol),
+                            Some(DataType::Func | DataType::Prototype)
+                        )
+                    })
+                    .ok_or(VerifyError::InvalidCall(address))?;
+                self.call(address, opcode, function, stack)?;
+            }
+            (Opcode::CallExtern, InstructionData::Symbol(function)) => {
+                if self.data_type(*function) != Some(DataType::Func) {
+                    return Err(VerifyError::InvalidCall(address));
+                }
+                self.call(address, opcode, *function, stack)?;
+            }
+            (Opcode::Bz, _) => {
+                pop(stack, address, opcode, DataType::Int)?;
+            }
+            (Opcode::PushInt, _) => stack.push(StackType::Int),
+            (Opcode::PushVar | Opcode::PushVarInstance, InstructionData::Symbol(symbol))
+            | (Opcode::PushVV, InstructionData::SymbolIndex { symbol, .. }) => {
+                let ty = self.symbol(address, *symbol)?.props.elem_props.data_type();
+                stack.push(StackType::Reference(ty));
+            }
+            (Opcode::GMovI, InstructionData::Symbol(symbol)) => {
+                self.symbol(address, *symbol)?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Arguments are pushed in order, so the last one is on top
+    fn call(
+        &self,
+        address: u32,
+        opcode: Opcode,
+        function: u32,
+        stack: &mut Vec<StackType>,
+    ) -> Result<(), VerifyError> {
+        for (_, ty) in self.params(address, function)?.into_iter().rev() {
+            pop(stack, address, opcode, ty)?;
+        }
+
+        let ret = self.symbol(address, function)?.return_type();
+        if ret != DataType::Void {
+            stack.push(value_type(ret));
+        }
+        Ok(())
+    }
+
+    fn symbol(&self, address: u32, symbol: u32) -> Result<&Symbol, VerifyError> {
+        self.dat
+            .symbols
+            .get(symbol as usize)
+            .ok_or(VerifyError::UnknownSymbol { address, symbol })
+    }
+
+    fn data_type(&self, symbol: u32) -> Option<DataType> {
+        self.dat
+            .symbols
+            .get(symbol as usize)
+            .map(|symbol| symbol.props.elem_props.data_type())
+    }
+
+    fn params(&self, address: u32, function: u32) -> Result<Vec<(u32, DataType)>, VerifyError> {
+        let params = self
+            .dat
+            .params(function)
+            .ok_or(VerifyError::UnknownSymbol {
+                address,
+                symbol: function,
+            })?;
+
+        Ok(params
+            .map(|param| {
+                let ty = self.dat.symbols[param as usize]
+                    .props
+                    .elem_props
+                    .data_type();
+                (param, ty)
+            })
+            .collect())
+    }
+}
+
+fn pop(
+    stack: &mut Vec<StackType>,
+    address: u32,
+    opcode: Opcode,
+    expected: DataType,
+) -> Result<StackType, VerifyError> {
+    let value = stack.pop().ok_or(VerifyError::StackUnderflow(address))?;
+    if !assignable(expected, value) {
+        return Err(VerifyError::TypeMismatch {
+            address,
+            opcode,
+            expected: type_name(expected),
+            found: value,
+        });
+    }
+    Ok(value)
+}
+
+/// Value a function returning or taking `ty` has on the stack
+fn value_type(ty: DataType) -> StackType {
+    match ty {
+        DataType::Float => StackType::Float,
+        DataType::String => StackType::String,
+        DataType::Instance | DataType::Prototype => StackType::Instance,
+        _ => StackType::Int,
+    }
+}
+
+/// Floats are ints as far as the code is concerned, and functions are passed around as their
+/// symbol index
+fn assignable(ty: DataType, value: StackType) -> bool {
+    use StackType as S;
+
+    match ty {
+        DataType::Int | DataType::Float | DataType::Func => matches!(
+            value,
+            S::Int | S::Float | S::Reference(DataType::Int | DataType::Float | DataType::Func)
+        ),
+        DataType::String => matches!(value, S::String | S::Reference(DataType::String)),
+        DataType::Instance | DataType::Prototype | DataType::Class => matches!(
+            value,
+            S::Instance | S::Reference(DataType::Instance | DataType::Prototype)
+        ),
+        DataType::Void => false,
+    }
+}
+
+fn type_name(ty: DataType) -> &'static str {
+    match ty {
+        DataType::Int | DataType::Func => "an int",
+        DataType::Float => "a float",
+        DataType::String => "a string",
+        _ => "an instance",
+    }
+}